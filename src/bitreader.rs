@@ -2,35 +2,62 @@ use std::io;
 use std::io::Cursor;
 use std::io::SeekFrom;
 
+use error;
+
 pub struct BitReader<'a> {
   pub cache: u8,
   pub cache_length: u32,
+  position: u64,
   reader: &'a mut (io::Read + 'a)
 }
 
 impl<'a> BitReader<'a> {
-  pub fn new(reader: &'a mut io::Read) -> BitReader<'a> {
-    return BitReader { cache: 0, cache_length: 0, reader: reader };
+  pub fn new(reader: &'a mut dyn io::Read) -> BitReader<'a> {
+    BitReader { cache: 0, cache_length: 0, position: 0, reader }
+  }
+
+  /// The number of bits read so far.
+  pub fn position(&self) -> u64 {
+    self.position
+  }
+
+  pub fn skip_bits(&mut self, n: u64) -> error::Result<()> {
+    let mut remaining = n;
+
+    while remaining > 0 {
+      let step = if remaining > 32 { 32 } else { remaining as u32 };
+
+      match self.read_bits(step) {
+        Ok(_) => {}, Err(e) => return Err(e)
+      };
+
+      remaining -= step as u64;
+    }
+
+    Ok(())
   }
 
-  fn read_bytes(&mut self, n: usize) -> io::Result<u64> {
+  fn read_bytes(&mut self, n: usize) -> error::Result<u64> {
     let mut data = 0u64;
     let mut buf = [0u8];
     for _ in (0..n) {
       match self.reader.read(&mut buf) {
+        Ok(0) => return Err(error::Error::FrameOverrun(None)),
         Ok(_) => {data = (data << 8) + (buf[0] as u64)},
-        Err(e) => return Err(e)
+        Err(e) => return Err(error::Error::Io(e))
       }
     }
 
     Ok(data)
   }
 
-  pub fn read_bits(&mut self, n: u32) -> io::Result<u32> {
+  pub fn read_bits(&mut self, n: u32) -> error::Result<u32> {
     if n > 32 {
-      panic!("You cannot request more than 32 bits into a u32");
+      return Err(error::Error::InvalidData("more than 32 bits requested at once", None));
     }
 
+    self.position += n as u64;
+
     if n <= self.cache_length {
       let result = self.cache >> (self.cache_length - n);
 
@@ -115,6 +142,21 @@ fn test_stream() {
   assert_eq!(r.read_bits(4).unwrap(), 0x1);
 }
 
+#[test]
+fn test_position_and_skip() {
+  let buf = Cursor::new(vec![0xEA, 0xBD, 0x21, 0x7F, 0x00]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = BitReader::new(&mut br);
+
+  assert_eq!(r.position(), 0);
+  assert_eq!(r.read_bits(3).unwrap(), 0x7);
+  assert_eq!(r.position(), 3);
+  r.skip_bits(22).unwrap();
+  assert_eq!(r.position(), 25);
+  assert_eq!(r.read_bits(7).unwrap(), 0x7F);
+  assert_eq!(r.position(), 32);
+}
+
 #[test]
 fn test_stream2() {
   let buf = Cursor::new(vec![0x30, 0xC8, 0x61]);
@@ -126,3 +168,23 @@ fn test_stream2() {
   assert_eq!(r.read_bits(6).unwrap(), 33);
   assert_eq!(r.read_bits(6).unwrap(), 33);
 }
+
+#[test]
+fn test_read_past_end() {
+  let buf = Cursor::new(vec![0xEA]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = BitReader::new(&mut br);
+
+  assert_eq!(r.read_bits(6).unwrap(), 0x3A);
+
+  match r.read_bits(4) {
+    Err(error::Error::FrameOverrun(None)) => {},
+    _ => panic!("read past the end")
+  };
+
+  match r.read_bits(33) {
+    Err(error::Error::InvalidData(_, None)) => {},
+    _ => panic!("read more than 32 bits")
+  };
+}
+
@@ -0,0 +1,82 @@
+/// How a frame that cannot be decoded is replaced: with silence, with the
+/// subband samples of the last decoded frame, or with those samples fading
+/// out, halving in level with every frame concealed in a row.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ConcealMethod {
+  Mute,
+  Repeat,
+  Fade
+}
+
+/// Keeps the subband samples of the last decoded frame to make up frames
+/// from.
+pub struct Concealer {
+  previous: Vec<[f64; 32]>,
+  gain: f64
+}
+
+impl Default for Concealer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Concealer {
+  pub fn new() -> Concealer {
+    Concealer { previous: Vec::new(), gain: 1.0 }
+  }
+
+  /// Keeps the subband samples of a decoded frame, channel after channel.
+  pub fn remember(&mut self, subband_samples: &[[f64; 32]]) {
+    self.previous = subband_samples.to_vec();
+    self.gain = 1.0;
+  }
+
+  /// Makes up `blocks` blocks of 32 subband samples for each channel. When
+  /// the last decoded frame was laid out differently there is nothing to
+  /// repeat, and the frame is muted.
+  pub fn conceal(&mut self, method: ConcealMethod, blocks: usize, num_channels: usize) -> Vec<[f64; 32]> {
+    if method == ConcealMethod::Mute || self.previous.len() != blocks * num_channels {
+      return vec![[0f64; 32]; blocks * num_channels];
+    }
+
+    let start = self.gain;
+    let end = if method == ConcealMethod::Fade { start / 2.0 } else { start };
+    let mut samples = self.previous.clone();
+
+    for (i, block) in samples.iter_mut().enumerate() {
+      let gain = start + (end - start) * ((i % blocks) + 1) as f64 / blocks as f64;
+
+      for sample in block.iter_mut() {
+        *sample *= gain;
+      }
+    }
+
+    self.gain = end;
+
+    samples
+  }
+}
+
+#[test]
+fn test_conceal() {
+  let mut concealer = Concealer::new();
+
+  // Nothing decoded yet.
+  assert_eq!(concealer.conceal(ConcealMethod::Repeat, 2, 1), vec![[0f64; 32]; 2]);
+
+  concealer.remember(&[[1f64; 32]; 4]);
+
+  assert_eq!(concealer.conceal(ConcealMethod::Repeat, 2, 2), vec![[1f64; 32]; 4]);
+  assert_eq!(concealer.conceal(ConcealMethod::Mute, 2, 2), vec![[0f64; 32]; 4]);
+  assert_eq!(concealer.conceal(ConcealMethod::Repeat, 4, 2), vec![[0f64; 32]; 8]);
+
+  // Each channel fades from the level reached by the last frame to half of it.
+  let faded = concealer.conceal(ConcealMethod::Fade, 2, 2);
+
+  assert_eq!(faded[0][0], 0.75);
+  assert_eq!(faded[1][0], 0.5);
+  assert_eq!(faded[2][0], 0.75);
+  assert_eq!(faded[3][0], 0.5);
+  assert_eq!(concealer.conceal(ConcealMethod::Fade, 2, 2)[1][0], 0.25);
+}
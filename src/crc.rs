@@ -0,0 +1,118 @@
+use header;
+use header::MpegLayer::*;
+use layer1;
+use layer2;
+use layer3;
+
+/// The outcome of the CRC check of a frame.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum CrcStatus {
+  Unprotected,
+  Valid,
+  Invalid
+}
+
+/// What to do with a frame that fails its CRC check: leave it out of the
+/// output, replace it with silence, or decode it as if nothing was wrong.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum CrcPolicy {
+  Skip,
+  Conceal,
+  DecodeAnyway
+}
+
+/// Continues `crc` over the first `bits` bits of `data` with the CRC-16
+/// polynomial 0x8005 (A.9 of ISO 11172-3).
+pub fn crc16(crc: u16, data: &[u8], bits: usize) -> u16 {
+  let mut crc = crc;
+
+  for i in 0..bits {
+    let bit = ((data[i / 8] >> (7 - i % 8)) & 1) as u16;
+    let carry = (crc >> 15) ^ bit;
+
+    crc <<= 1;
+
+    if carry != 0 {
+      crc ^= 0x8005;
+    }
+  }
+
+  crc
+}
+
+/// Checks the CRC of the frame `data`, which starts with `frame_header`. The
+/// CRC covers the last two bytes of the header and the protected bits of the
+/// layer that follow the check word.
+pub fn check_frame(frame_header: &header::Header, data: &[u8]) -> CrcStatus {
+  if !frame_header.crc {
+    return CrcStatus::Unprotected;
+  }
+
+  if data.len() < 6 {
+    return CrcStatus::Invalid;
+  }
+
+  let protected = &data[6..];
+
+  let bits = match frame_header.layer {
+    LayerI => layer1::protected_bits(frame_header),
+    LayerII => match layer2::protected_bits(frame_header, protected) {
+      Ok(b) => b,
+      Err(_) => return CrcStatus::Invalid
+    },
+    LayerIII => layer3::protected_bits(frame_header),
+    _ => return CrcStatus::Invalid
+  };
+
+  if bits > protected.len() * 8 {
+    return CrcStatus::Invalid;
+  }
+
+  let crc = crc16(crc16(0xffff, &data[2..4], 16), protected, bits);
+  let check = ((data[4] as u16) << 8) | data[5] as u16;
+
+  if crc == check { CrcStatus::Valid } else { CrcStatus::Invalid }
+}
+
+#[test]
+fn test_crc16() {
+  // The CRC-16 of "123456789" with an initial value of 0xffff and no final
+  // inversion is 0xaee7.
+  assert_eq!(crc16(0xffff, b"123456789", 72), 0xaee7);
+
+  // Bits past `bits` are ignored.
+  assert_eq!(crc16(0xffff, &[0xff, 0x80], 9), crc16(0xffff, &[0xff, 0xff], 9));
+}
+
+#[test]
+fn test_layer1_frame() {
+  // The header and start of the first frame of a protected stereo Layer I
+  // stream, whose bit allocations cover 256 bits.
+  let mut data = vec![0u8; 64];
+  data[..6].copy_from_slice(&[0xff, 0xfe, 0xc8, 0x04, 0x00, 0x00]);
+
+  for (i, byte) in data.iter_mut().enumerate().take(38).skip(6) {
+    *byte = (i * 37) as u8;
+  }
+
+  let crc = crc16(crc16(0xffff, &data[2..4], 16), &data[6..38], 256);
+  data[4] = (crc >> 8) as u8;
+  data[5] = crc as u8;
+
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFEC804)).unwrap();
+
+  assert_eq!(check_frame(&frame_header, &data), CrcStatus::Valid);
+
+  data[37] ^= 1;
+
+  assert_eq!(check_frame(&frame_header, &data), CrcStatus::Invalid);
+
+  // Bits after the allocations are not covered.
+  data[37] ^= 1;
+  data[38] ^= 1;
+
+  assert_eq!(check_frame(&frame_header, &data), CrcStatus::Valid);
+
+  // A frame too short for its allocations fails.
+  assert_eq!(check_frame(&frame_header, &data[..37]), CrcStatus::Invalid);
+}
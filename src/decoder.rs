@@ -0,0 +1,1340 @@
+use std::io;
+use std::io::Read;
+use std::io::SeekFrom;
+
+use conceal;
+use crc;
+use deemphasis;
+use error;
+use frame;
+use header;
+use header::MpegLayer::*;
+use id3;
+use layer1;
+use layer2;
+use layer3;
+use synthesis;
+use tags;
+use vbr;
+
+/// The number of bytes read from the source at a time.
+const READ_SIZE: usize = 4096;
+
+/// The delay of the decoder in samples, which encoders count on besides
+/// their own delay for gapless playback.
+const DECODER_DELAY: u64 = 529;
+
+/// What `StreamDecoder::next_frame` has to offer.
+pub enum Next {
+  Frame(Box<frame::MpegFrame>),
+  NeedMoreData,
+  /// The stream is finished and every frame fed was taken.
+  End,
+  Error(error::Error)
+}
+
+/// Decodes a stream that is pushed to it in chunks of any size, keeping
+/// partial frames until the rest of them arrives. Along with the frames it
+/// keeps the per-channel state that carries over from one frame to the next.
+pub struct StreamDecoder {
+  buffer: Vec<u8>,
+  finished: bool,
+  frames: frame::FrameReader,
+  synthesis: [synthesis::SynthesisFilter; 2],
+  layer3: layer3::Layer3Decoder,
+  crc_policy: crc::CrcPolicy,
+  deemphasis: Option<[deemphasis::DeemphasisFilter; 2]>,
+  concealer: conceal::Concealer,
+  concealment: conceal::ConcealMethod,
+  /// The bytes skipped since the last frame, and the size of that frame.
+  skipped: usize,
+  last_frame_size: usize,
+  /// The bytes skipped since the last frame or tag, up to the largest tag,
+  /// which hold the items of an APEv2 tag when only its footer is found.
+  junk: Vec<u8>,
+  /// The offset in the stream of the start of the buffer, and the index of
+  /// the next frame.
+  offset: u64,
+  frame_index: u64,
+  gapless: bool,
+  /// The samples still to be trimmed from the start of the output, and the
+  /// samples left to output before the end is trimmed.
+  trim_start: u64,
+  remaining: Option<u64>,
+  /// The samples trimmed from the start of the stream and the samples of
+  /// the whole stream once trimmed, kept for seeking.
+  lead: u64,
+  total: Option<u64>,
+  /// The index of the first frame after those that the last VBR header
+  /// counts, where the next stream starts when streams are put one after
+  /// another.
+  stream_end: Option<u64>,
+  /// The offsets of the frames parsed from the start of the stream on, the
+  /// index of the first frame that is not a VBR header, and its header.
+  index: Vec<u64>,
+  first_audio_frame: u64,
+  audio_header: Option<header::Header>,
+  /// Whether the audio frames in the index all have the bitrate of the
+  /// first.
+  constant_bitrate: bool,
+  /// The VBR header of the first frame, whose seek table is used for seeking.
+  vbr_header: Option<vbr::VbrHeader>,
+  /// The ID3v2 tag in front of the first frame, and the tags found after
+  /// it.
+  id3v2: Option<id3::Tag>,
+  tags: Vec<tags::Tag>
+}
+
+impl Default for StreamDecoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl StreamDecoder {
+  pub fn new() -> StreamDecoder {
+    StreamDecoder {
+      buffer: Vec::new(),
+      finished: false,
+      frames: frame::FrameReader::new(),
+      synthesis: [synthesis::SynthesisFilter::new(), synthesis::SynthesisFilter::new()],
+      layer3: layer3::Layer3Decoder::new(),
+      crc_policy: crc::CrcPolicy::Conceal,
+      deemphasis: None,
+      concealer: conceal::Concealer::new(),
+      concealment: conceal::ConcealMethod::Mute,
+      skipped: 0,
+      last_frame_size: 0,
+      junk: Vec::new(),
+      offset: 0,
+      frame_index: 0,
+      gapless: true,
+      trim_start: 0,
+      remaining: None,
+      lead: 0,
+      total: None,
+      stream_end: None,
+      index: Vec::new(),
+      first_audio_frame: 0,
+      audio_header: None,
+      constant_bitrate: true,
+      vbr_header: None,
+      id3v2: None,
+      tags: Vec::new()
+    }
+  }
+
+  /// Turns the de-emphasis of streams recorded with emphasis on or off. It
+  /// is off by default, leaving such streams too bright.
+  pub fn set_deemphasis(&mut self, enabled: bool) {
+    self.deemphasis = if enabled {
+      Some([deemphasis::DeemphasisFilter::new(), deemphasis::DeemphasisFilter::new()])
+    } else {
+      None
+    };
+  }
+
+  /// Chooses what happens to frames that fail their CRC check. They are
+  /// concealed by default.
+  pub fn set_crc_policy(&mut self, policy: crc::CrcPolicy) {
+    self.crc_policy = policy;
+  }
+
+  /// Chooses how frames that are lost, corrupt or fail their CRC check are
+  /// made up. They are muted by default.
+  pub fn set_concealment(&mut self, method: conceal::ConcealMethod) {
+    self.concealment = method;
+  }
+
+  /// Turns the trimming of the encoder delay and padding given by a LAME
+  /// tag on or off. It is on by default.
+  pub fn set_gapless(&mut self, enabled: bool) {
+    self.gapless = enabled;
+  }
+
+  /// The ID3v2 tag found in front of the first frame, once that frame is
+  /// parsed. A tag that cannot be read is skipped all the same.
+  pub fn id3v2(&self) -> Option<&id3::Tag> {
+    self.id3v2.as_ref()
+  }
+
+  /// The tags found among the frames parsed so far other than the ID3v2 tag
+  /// in front of them, which mostly means the ID3v1, Lyrics3 and APEv2 tags
+  /// after the last frame. Tags that cannot be read are left out.
+  pub fn tags(&self) -> &[tags::Tag] {
+    &self.tags
+  }
+
+  /// Adds the next chunk of the stream.
+  pub fn feed(&mut self, data: &[u8]) {
+    self.buffer.extend(data.iter().cloned());
+  }
+
+  /// Says that nothing follows what was fed so far, so that a last frame
+  /// that is cut short is reported rather than waited for.
+  pub fn finish(&mut self) {
+    self.finished = true;
+  }
+
+  /// Takes the next complete frame from what was fed so far. Data between
+  /// frames that belongs to none of them is skipped. After `finish`, End is
+  /// returned once nothing is left.
+  pub fn next_frame(&mut self) -> Next {
+    match self.parse() {
+      Ok(frame::Parsed::Frame(f)) => Next::Frame(Box::new(f)),
+      Ok(_) => {
+        if !self.finished {
+          return Next::NeedMoreData;
+        }
+
+        match self.check_end() {
+          Ok(_) => Next::End,
+          Err(e) => Next::Error(e)
+        }
+      },
+      Err(e) => Next::Error(e)
+    }
+  }
+
+  /// Checks what is left once the stream is over. A last frame cut short is
+  /// an error, and so is a stream in which no frame was found at all. Each
+  /// is reported once.
+  fn check_end(&mut self) -> error::Result<()> {
+    if !self.buffer.is_empty() {
+      let e = error::Error::UnexpectedEof(None).at(self.offset, self.frame_index);
+
+      self.offset += self.buffer.len() as u64;
+      self.buffer.clear();
+
+      return Err(e);
+    }
+
+    if self.frame_index == 0 && self.skipped > 0 {
+      self.skipped = 0;
+
+      return Err(error::Error::InvalidHeader(None).at(0, 0));
+    }
+
+    Ok(())
+  }
+
+  /// Parses the frame at the start of the buffer, taking it out of the
+  /// buffer when it is complete. Junk in front of the frame is dropped, so
+  /// that only a frame or the need for more data is returned.
+  fn parse(&mut self) -> error::Result<frame::Parsed> {
+    loop {
+      let parsed = self.frames.parse(&self.buffer, self.finished)?;
+
+      match parsed {
+        frame::Parsed::Frame(mut f) => {
+          self.buffer.drain(..f.len());
+
+          // Junk between frames is taken for as many frames as it would hold.
+          if let Some(lost) = (self.skipped + self.last_frame_size / 2).checked_div(self.last_frame_size) {
+            f.lost_frames = lost;
+          }
+
+          f.offset = self.offset;
+          f.index = self.frame_index;
+
+          let indexed = f.index == self.index.len() as u64;
+
+          if indexed {
+            self.index.push(f.offset);
+          }
+
+          // A VBR header is only looked for in the first frame and in the
+          // frame that the last one says the next stream starts with, so that
+          // a frame in the middle of a stream is never taken for one.
+          if f.index == 0 || Some(f.index) == self.stream_end {
+            f.read_vbr_header();
+
+            if let Some(ref vbr) = f.vbr_header {
+              self.stream_end = vbr.frames.map(|frames| f.index + 1 + frames as u64);
+
+              if f.index == 0 {
+                self.first_audio_frame = 1;
+                self.vbr_header = Some(vbr.clone());
+              }
+            }
+          }
+
+          if f.index == self.first_audio_frame {
+            self.audio_header = Some(f.header.clone());
+          } else if indexed && f.index > self.first_audio_frame && self.audio_header.as_ref().map(|h| h.bitrate) != Some(f.header.bitrate) {
+            self.constant_bitrate = false;
+          }
+
+          self.skipped = 0;
+          self.junk.clear();
+          self.last_frame_size = f.len();
+          self.offset += f.len() as u64;
+          self.frame_index += 1;
+
+          return Ok(frame::Parsed::Frame(f));
+        },
+        frame::Parsed::Junk(n) => {
+          self.junk.extend(self.buffer.drain(..n));
+
+          if self.junk.len() > tags::MAX_TAG_SIZE {
+            let excess = self.junk.len() - tags::MAX_TAG_SIZE;
+
+            self.junk.drain(..excess);
+          }
+
+          self.skipped += n;
+          self.offset += n as u64;
+        },
+        frame::Parsed::Tag(n, tag) => {
+          // An APEv2 tag without a header is only found at its footer, once
+          // its items were skipped, which are not lost frames.
+          let tag = match tags::ape_items_size(&self.buffer[..n]) {
+            Some(size) if size <= self.junk.len() => {
+              self.skipped = self.skipped.saturating_sub(size);
+
+              tags::parse_ape_footer(&self.junk[self.junk.len() - size..], &self.buffer[..n])
+            },
+            _ => tag
+          };
+
+          self.junk.clear();
+          self.buffer.drain(..n);
+          self.offset += n as u64;
+
+          match tag {
+            Some(tags::Tag::Id3v2(tag)) if self.frame_index == 0 && self.id3v2.is_none() => self.id3v2 = Some(tag),
+            Some(tag) => self.tags.push(tag),
+            None => {}
+          };
+        },
+        _ => return Ok(parsed)
+      };
+    }
+  }
+
+  /// Enters the stream at the frame at `offset` with index `frame_index`,
+  /// dropping what was buffered and the state left by previous frames.
+  fn reset(&mut self, offset: u64, frame_index: u64) {
+    self.buffer.clear();
+    self.finished = false;
+    self.frames.resync();
+
+    for filter in self.synthesis.iter_mut() {
+      filter.reset();
+    }
+
+    self.layer3.reset();
+
+    if self.deemphasis.is_some() {
+      self.set_deemphasis(true);
+    }
+
+    self.concealer = conceal::Concealer::new();
+    self.skipped = 0;
+    self.last_frame_size = 0;
+    self.junk.clear();
+    self.offset = offset;
+    self.frame_index = frame_index;
+  }
+
+  /// Decodes the payload of `frame`, returning one vector of samples in the
+  /// range [-1.0, 1.0] per channel and keeping the ancillary data of Layer I
+  /// and II frames in `frame`.
+  ///
+  /// Frames lost before `frame` are concealed ahead of its own samples. A
+  /// frame that fails to decode is concealed as well, and so is a frame
+  /// failing its CRC check unless the CRC policy says otherwise. Why the
+  /// frame was concealed is noted in `frame`. A frame holding a VBR header
+  /// has no samples.
+  pub fn decode_frame(&mut self, frame: &mut frame::MpegFrame) -> error::Result<Vec<Vec<f64>>> {
+    let num_channels = frame.header.num_channels();
+    let blocks = frame.header.frame_samples().unwrap_or(1152) as usize / 32;
+    let mut pcm = vec![Vec::new(); num_channels];
+
+    for _ in 0..frame.lost_frames {
+      let subband_samples = self.concealer.conceal(self.concealment, blocks, num_channels);
+
+      self.synthesize(&subband_samples, &frame.header, &mut pcm);
+    }
+
+    // A VBR header takes the place of the audio of its frame.
+    if let Some(ref vbr) = frame.vbr_header {
+      self.start_gapless(vbr, &frame.header, frame.index == 0);
+
+      return Ok(pcm);
+    }
+
+    // Frames past the end of the stream that the VBR header describes, and
+    // that no VBR header of their own describes, are output as they are.
+    if self.stream_end.is_some_and(|end| frame.index >= end) {
+      self.trim_start = 0;
+      self.remaining = None;
+    }
+
+    let crc_failed = frame.crc == crc::CrcStatus::Invalid && self.crc_policy != crc::CrcPolicy::DecodeAnyway;
+
+    let subband_samples = if crc_failed {
+      frame.concealed = Some(error::Error::CrcMismatch(None).at(frame.offset, frame.index));
+
+      // Layer III frames still carry main data for the frames after them.
+      if frame.header.layer == LayerIII {
+        let _ = self.layer3.skip_frame(frame.payload(), &frame.header);
+      }
+
+      if self.crc_policy == crc::CrcPolicy::Skip {
+        self.trim(&mut pcm);
+
+        return Ok(pcm);
+      }
+
+      self.concealer.conceal(self.concealment, blocks, num_channels)
+    } else {
+      match self.decode_payload(frame.payload(), &frame.header, blocks) {
+        Ok((samples, ancillary)) => {
+          self.concealer.remember(&samples);
+          frame.set_ancillary(ancillary);
+
+          samples
+        },
+        Err(e) => {
+          frame.concealed = Some(e.at(frame.offset, frame.index));
+
+          self.concealer.conceal(self.concealment, blocks, num_channels)
+        }
+      }
+    };
+
+    self.synthesize(&subband_samples, &frame.header, &mut pcm);
+    self.trim(&mut pcm);
+
+    Ok(pcm)
+  }
+
+  /// Sets up the trimming of the stream that `vbr` describes, when it has a
+  /// LAME tag. The tag gives the samples the encoder added before and after
+  /// the audio, while the header gives the number of frames. Seeking only
+  /// counts on the `first` stream, in front of any put after it.
+  fn start_gapless(&mut self, vbr: &vbr::VbrHeader, frame_header: &header::Header, first: bool) {
+    let lame = match vbr.lame {
+      Some(ref lame) if self.gapless => lame,
+      _ => {
+        self.trim_start = 0;
+        self.remaining = None;
+        return;
+      }
+    };
+
+    self.trim_start = lame.encoder_delay as u64 + DECODER_DELAY;
+
+    self.remaining = match (vbr.frames, frame_header.frame_samples()) {
+      (Some(frames), Some(samples)) => {
+        let total = frames as u64 * samples as u64;
+        let added = lame.encoder_delay as u64 + lame.padding as u64;
+
+        Some(total.saturating_sub(added))
+      },
+      _ => None
+    };
+
+    if first {
+      self.lead = self.trim_start;
+      self.total = self.remaining;
+    }
+  }
+
+  /// Drops the samples of `pcm` that fall before the start or after the end
+  /// of the audio of a gapless stream.
+  fn trim(&mut self, pcm: &mut [Vec<f64>]) {
+    let length = pcm[0].len() as u64;
+    let start = if self.trim_start < length { self.trim_start } else { length };
+    let mut end = length;
+
+    self.trim_start -= start;
+
+    if let Some(remaining) = self.remaining {
+      if start + remaining < end {
+        end = start + remaining;
+      }
+
+      self.remaining = Some(remaining - (end - start));
+    }
+
+    for channel in pcm.iter_mut() {
+      channel.truncate(end as usize);
+      channel.drain(..start as usize);
+    }
+  }
+
+  /// Decodes the subband samples of a frame, channel after channel, along
+  /// with its ancillary data.
+  fn decode_payload(&mut self, payload: &[u8], frame_header: &header::Header, blocks: usize) -> error::Result<(Vec<[f64; 32]>, Vec<u8>)> {
+    let num_channels = frame_header.num_channels();
+
+    match frame_header.layer {
+      LayerI => {
+        let (samples, ancillary) = layer1::decode_layer1(payload, frame_header)?;
+
+        Ok((samples[..num_channels].iter().flat_map(|c| c.iter().cloned()).collect(), ancillary))
+      },
+      LayerII => {
+        let (samples, ancillary) = layer2::decode_layer2(payload, frame_header)?;
+
+        Ok((samples[..num_channels].iter().flat_map(|c| c.iter().cloned()).collect(), ancillary))
+      },
+      LayerIII => {
+        let samples = self.layer3.decode_layer3(payload, frame_header)?;
+
+        // LSF frames only fill the first of the two granules.
+        Ok((samples[..num_channels].iter().flat_map(|c| c[..blocks].iter().cloned()).collect(), Vec::new()))
+      },
+      _ => Err(error::Error::UnsupportedLayer(None))
+    }
+  }
+
+  /// Runs subband samples laid out channel after channel through the
+  /// synthesis filterbank, adding the PCM to that of each channel.
+  fn synthesize(&mut self, subband_samples: &[[f64; 32]], frame_header: &header::Header, pcm: &mut [Vec<f64>]) {
+    let num_channels = pcm.len();
+    let samples_per_channel = subband_samples.len() / num_channels;
+
+    for channel in 0..num_channels {
+      let mut output = vec![0f64; samples_per_channel * 32];
+
+      for sample in 0..samples_per_channel {
+        let input = &subband_samples[channel * samples_per_channel + sample];
+
+        self.synthesis[channel].synthesize(input, &mut output[sample * 32..(sample + 1) * 32]);
+      }
+
+      if let Some(ref mut filters) = self.deemphasis { filters[channel].process(frame_header.emphasis, frame_header.samplerate.unwrap_or(0), &mut output) }
+
+      pcm[channel].extend(output);
+    }
+  }
+}
+
+/// Reads the frames of a stream from any source and turns them into PCM. The
+/// source is only seeked by `seek`: what is read ahead of the current frame
+/// is kept in a buffer.
+pub struct Decoder<R: io::Read> {
+  reader: R,
+  stream: StreamDecoder
+}
+
+impl<R: io::Read> Decoder<R> {
+  pub fn new(reader: R) -> Decoder<R> {
+    Decoder { reader, stream: StreamDecoder::new() }
+  }
+
+  /// See `StreamDecoder::set_deemphasis`.
+  pub fn set_deemphasis(&mut self, enabled: bool) {
+    self.stream.set_deemphasis(enabled);
+  }
+
+  /// See `StreamDecoder::set_crc_policy`.
+  pub fn set_crc_policy(&mut self, policy: crc::CrcPolicy) {
+    self.stream.set_crc_policy(policy);
+  }
+
+  /// See `StreamDecoder::set_concealment`.
+  pub fn set_concealment(&mut self, method: conceal::ConcealMethod) {
+    self.stream.set_concealment(method);
+  }
+
+  /// See `StreamDecoder::set_gapless`.
+  pub fn set_gapless(&mut self, enabled: bool) {
+    self.stream.set_gapless(enabled);
+  }
+
+  /// The ID3v2 tag found in front of the first frame, once that frame is
+  /// read.
+  pub fn id3v2(&self) -> Option<&id3::Tag> {
+    self.stream.id3v2()
+  }
+
+  /// The tags found among the frames read so far other than the ID3v2 tag
+  /// in front of them, which are all there once None is returned.
+  pub fn tags(&self) -> &[tags::Tag] {
+    self.stream.tags()
+  }
+
+  /// Reads the next frame, skipping junk between frames, and returns None at
+  /// the end of the stream.
+  pub fn next_frame(&mut self) -> error::Result<Option<frame::MpegFrame>> {
+    loop {
+      match self.stream.parse() {
+        Ok(frame::Parsed::Frame(f)) => return Ok(Some(f)),
+        Ok(_) => {
+          if self.stream.finished {
+            return match self.stream.check_end() {
+              Ok(_) => Ok(None),
+              Err(e) => Err(e)
+            };
+          }
+
+          match self.fill_buffer() {
+            Ok(_) => {}, Err(e) => return Err(e)
+          };
+        },
+        Err(e) => return Err(e)
+      };
+    }
+  }
+
+  /// Reads more of the source into the buffer, noting when it ends.
+  fn fill_buffer(&mut self) -> error::Result<()> {
+    let mut chunk = [0u8; READ_SIZE];
+
+    match self.reader.read(&mut chunk) {
+      Ok(0) => {
+        self.stream.finish();
+        Ok(())
+      },
+      Ok(n) => {
+        self.stream.feed(&chunk[..n]);
+        Ok(())
+      },
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+      Err(e) => Err(error::Error::Io(e))
+    }
+  }
+
+  /// See `StreamDecoder::decode_frame`.
+  pub fn decode_frame(&mut self, frame: &mut frame::MpegFrame) -> error::Result<Vec<Vec<f64>>> {
+    self.stream.decode_frame(frame)
+  }
+}
+
+impl<R: io::Read + io::Seek> Decoder<R> {
+  /// Moves to sample `sample_index` of the output, counted after gapless
+  /// trimming, so that the output of the next frame decoded starts with it.
+  ///
+  /// The frame holding the sample is found in the index of the frames read
+  /// so far, at its offset in a constant bitrate stream, or else by reading
+  /// on through the frame headers, which extends the index. The seek table
+  /// of a Xing header is too coarse to tell which frame starts where, and is
+  /// not used. Decoding starts a few frames earlier to fill the synthesis filterbank and, in Layer
+  /// III, the IMDCT overlap and the bit reservoir, and the samples ahead of
+  /// the target are trimmed.
+  pub fn seek(&mut self, sample_index: u64) -> error::Result<()> {
+    // The first frames tell whether a VBR header comes first, how much of
+    // the output is trimmed, and what the frames of the stream are like.
+    while self.stream.audio_header.is_none() {
+      match self.next_frame() {
+        Ok(Some(mut f)) => if f.vbr_header.is_some() {
+          match self.decode_frame(&mut f) { Ok(_) => {}, Err(e) => return Err(e) };
+        },
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(e)
+      };
+    }
+
+    let audio_header = match self.stream.audio_header { Some(ref h) => h.clone(), None => return Ok(()) };
+    let samples = audio_header.frame_samples().unwrap_or(1152) as u64;
+    let first_audio_frame = self.stream.first_audio_frame;
+    let position = sample_index + self.stream.lead;
+    let target = first_audio_frame + position / samples;
+    let preroll = preroll_frames(&audio_header);
+    let first = if target > first_audio_frame + preroll { target - preroll } else { first_audio_frame };
+
+    let base = match self.reader.stream_position() {
+      Ok(p) => p - (self.stream.offset + self.stream.buffer.len() as u64),
+      Err(e) => return Err(error::Error::Io(e))
+    };
+
+    let offset = match self.frame_offset(base, first, &audio_header) {
+      Ok(Some(o)) => o,
+      Ok(None) => {
+        // Past the end of the stream, where there is nothing left to decode.
+        self.stream.remaining = Some(0);
+        return Ok(());
+      },
+      Err(e) => return Err(e)
+    };
+
+    match self.reposition(base, offset, first) {
+      Ok(_) => {}, Err(e) => return Err(e)
+    };
+
+    self.stream.trim_start = position - (first - first_audio_frame) * samples;
+    self.stream.remaining = self.stream.total.map(|total| total.saturating_sub(sample_index));
+
+    Ok(())
+  }
+
+  /// Seeks to the start of the chapter with the element ID `element_id` in
+  /// the ID3v2 tag, returning false when there is no such chapter.
+  pub fn seek_to_chapter(&mut self, element_id: &str) -> error::Result<bool> {
+    // The tag and the sample rate are known once the first frames are read.
+    match self.seek(0) {
+      Ok(_) => {}, Err(e) => return Err(e)
+    };
+
+    let chapter = self.stream.id3v2.as_ref().and_then(|tag| tag.chapter(element_id));
+    let samplerate = self.stream.audio_header.as_ref().and_then(|h| h.samplerate);
+
+    let sample_index = match (chapter, samplerate) {
+      (Some(chapter), Some(samplerate)) => chapter.start_sample(samplerate),
+      _ => return Ok(false)
+    };
+
+    self.seek(sample_index).map(|_| true)
+  }
+
+  /// Finds the offset of frame `frame`, or None when the stream ends before.
+  fn frame_offset(&mut self, base: u64, frame: u64, audio_header: &header::Header) -> error::Result<Option<u64>> {
+    if frame < self.stream.index.len() as u64 {
+      return Ok(Some(self.stream.index[frame as usize]));
+    }
+
+    // Frames are only found from their sizes when the bitrate is known to
+    // be constant, from an Info tag or from the frames indexed so far.
+    let constant_bitrate = match self.stream.vbr_header {
+      Some(ref vbr) => vbr.tag == vbr::VbrTag::Info,
+      None => self.stream.constant_bitrate
+    };
+
+    if constant_bitrate && !audio_header.free_format {
+      match self.find_cbr_frame(base, frame, audio_header) {
+        Ok(Some(offset)) => return Ok(Some(offset)),
+        Ok(None) => {},
+        Err(e) => return Err(e)
+      };
+    }
+
+    // Reading on through the headers of the frames after the last one in
+    // the index adds the frames up to the one looked for, and where junk or
+    // a tag gets in the way, parsing them does.
+    self.walk_frames(base, frame, audio_header)?;
+
+    if frame < self.stream.index.len() as u64 {
+      return Ok(Some(self.stream.index[frame as usize]));
+    }
+
+    let last = self.stream.index.len() as u64 - 1;
+    let last_offset = self.stream.index[last as usize];
+
+    match self.reposition(base, last_offset, last) {
+      Ok(_) => {}, Err(e) => return Err(e)
+    };
+
+    while self.stream.index.len() as u64 <= frame {
+      match self.next_frame() {
+        Ok(Some(_)) => {},
+        Ok(None) | Err(error::Error::UnexpectedEof(_)) => return Ok(None),
+        Err(e) => return Err(e)
+      };
+    }
+
+    Ok(Some(self.stream.index[frame as usize]))
+  }
+
+  /// Finds frame `frame` of a constant bitrate stream, whose frames are the
+  /// same size but for padding. It has to start with a header like that of
+  /// the first audio frame close to where the sizes put it, which another
+  /// such header or the end of the stream follows.
+  fn find_cbr_frame(&mut self, base: u64, frame: u64, audio_header: &header::Header) -> error::Result<Option<u64>> {
+    let (bitrate, samplerate, samples) = match (audio_header.bitrate, audio_header.samplerate, audio_header.frame_samples()) {
+      (Some(b), Some(s), Some(f)) => (b as f64, s as f64, f as f64),
+      _ => return Ok(None)
+    };
+
+    let first_audio_frame = self.stream.first_audio_frame;
+    let average_size = samples / 8.0 * bitrate * 1000.0 / samplerate;
+    let estimate = self.stream.index[first_audio_frame as usize] + ((frame - first_audio_frame) as f64 * average_size) as u64;
+    let window = estimate.saturating_sub(CBR_WINDOW);
+
+    let mut data = Vec::new();
+
+    match self.reader.seek(SeekFrom::Start(base + window)) {
+      Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+    };
+
+    // Enough for the frame and the header after it, padding included.
+    let limit = 2 * CBR_WINDOW + average_size as u64 + 12;
+
+    match self.reader.by_ref().take(limit).read_to_end(&mut data) {
+      Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+    };
+
+    let end = (data.len() as u64) < limit;
+
+    let frame_size = |i: usize| -> Option<usize> {
+      if i + 4 > data.len() {
+        return None;
+      }
+
+      match header::Header::from_bytes(&data[i..]) {
+        Some(ref h) if h.is_valid() && h.is_consistent_with(audio_header) && h.bitrate == audio_header.bitrate => h.frame_size().map(|size| size as usize),
+        _ => None
+      }
+    };
+
+    let matches = |at: u64| -> bool {
+      let i = (at - window) as usize;
+
+      match frame_size(i) {
+        Some(size) => (end && i + size == data.len()) || frame_size(i + size).is_some(),
+        None => false
+      }
+    };
+
+    for distance in 0..CBR_WINDOW + 1 {
+      if distance <= estimate - window && matches(estimate - distance) {
+        return Ok(Some(estimate - distance));
+      }
+
+      if matches(estimate + distance) {
+        return Ok(Some(estimate + distance));
+      }
+    }
+
+    Ok(None)
+  }
+
+  /// Adds the frames after the last one in the index, up to frame `frame`,
+  /// to the index from their headers alone. It stops at anything but a
+  /// header like that of the first audio frame and at a frame cut short by
+  /// the end of the stream.
+  fn walk_frames(&mut self, base: u64, frame: u64, audio_header: &header::Header) -> error::Result<()> {
+    let length = match self.reader.seek(SeekFrom::End(0)) {
+      Ok(end) => end - base,
+      Err(e) => return Err(error::Error::Io(e))
+    };
+
+    let last = self.stream.index[self.stream.index.len() - 1];
+    let mut offset = last;
+    let mut bytes = [0u8; 4];
+
+    while (self.stream.index.len() as u64) <= frame {
+      match self.reader.seek(SeekFrom::Start(base + offset)).and_then(|_| self.reader.read_exact(&mut bytes)) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(e) => return Err(error::Error::Io(e))
+      };
+
+      let (size, bitrate) = match header::Header::from_bytes(&bytes) {
+        Some(ref h) if h.is_valid() && h.is_consistent_with(audio_header) => (h.frame_size(), h.bitrate),
+        _ => return Ok(())
+      };
+
+      let size = match size {
+        Some(s) if s >= 4 && offset + s as u64 <= length => s as u64,
+        _ => return Ok(())
+      };
+
+      if offset > last {
+        self.stream.index.push(offset);
+
+        if bitrate != audio_header.bitrate {
+          self.stream.constant_bitrate = false;
+        }
+      }
+
+      offset += size;
+    }
+
+    Ok(())
+  }
+
+  /// Moves the source to the frame at `offset` with index `frame_index`.
+  fn reposition(&mut self, base: u64, offset: u64, frame_index: u64) -> error::Result<()> {
+    self.stream.reset(offset, frame_index);
+
+    match self.reader.seek(SeekFrom::Start(base + offset)) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(error::Error::Io(e))
+    }
+  }
+}
+
+/// The number of bytes around its expected offset in which a frame of a
+/// constant bitrate stream is looked for.
+const CBR_WINDOW: u64 = 8;
+
+/// The number of frames to decode ahead of a frame sought. The synthesis
+/// filterbank holds 512 samples, and a Layer III frame may take its main
+/// data from as far back as `MAX_MAIN_DATA_BEGIN` bytes, which with the
+/// lowest bitrate spans several frames.
+fn preroll_frames(frame_header: &header::Header) -> u64 {
+  match frame_header.layer {
+    LayerI => 2,
+    LayerII => 1,
+    _ => {
+      let lowest_bitrate = if frame_header.version == header::MpegVersion::MPEG1_0 { 32 } else { 8 };
+      let samples = frame_header.frame_samples().unwrap_or(1152) as u64;
+      let samplerate = frame_header.samplerate.unwrap_or(48000) as u64;
+      let overhead = (frame_header.length() + layer3::side_info_length(frame_header)) as u64;
+      let smallest = samples / 8 * lowest_bitrate * 1000 / samplerate;
+      let main_data = if smallest > overhead + 1 { smallest - overhead } else { 1 };
+
+      // One more frame fills the IMDCT overlap.
+      1 + (layer3::MAX_MAIN_DATA_BEGIN as u64).div_ceil(main_data)
+    }
+  }
+}
+
+#[cfg(test)]
+fn silent_layer1_stream(frames: usize) -> Vec<u8> {
+  // Mono 32 kbit/s frames at 32 kHz of 48 bytes, in which nothing is
+  // allocated.
+  let mut data = vec![0u8; 48 * frames];
+
+  for frame in 0..frames {
+    data[frame * 48..frame * 48 + 4].copy_from_slice(&[0xFF, 0xFF, 0x18, 0xC0]);
+  }
+
+  data
+}
+
+#[test]
+fn test_decode_from_slice() {
+  let data = silent_layer1_stream(3);
+  let mut decoder = Decoder::new(&data[..]);
+  let mut frames = 0;
+
+  while let Some(mut frame) = decoder.next_frame().unwrap() {
+    let pcm = decoder.decode_frame(&mut frame).unwrap();
+
+    assert_eq!(pcm.len(), 1);
+    assert_eq!(pcm[0], vec![0f64; 384]);
+    frames += 1;
+  }
+
+  assert_eq!(frames, 3);
+}
+
+#[test]
+fn test_resync() {
+  // Leading junk, and a damaged header in the middle of the stream.
+  let mut data = vec![0x12u8, 0xFF, 0xFF, 0x34, 0xFF];
+
+  data.extend(silent_layer1_stream(5));
+  data[5 + 2 * 48 + 2] = 0xFF;
+
+  let mut decoder = Decoder::new(&data[..]);
+  let mut frames = 0;
+  let mut samples = 0;
+
+  while let Some(mut frame) = decoder.next_frame().unwrap() {
+    assert_eq!(frame.lost_frames, if frames == 2 { 1 } else { 0 });
+
+    samples += decoder.decode_frame(&mut frame).unwrap()[0].len();
+    frames += 1;
+  }
+
+  // The damaged frame is concealed.
+  assert_eq!(frames, 4);
+  assert_eq!(samples, 5 * 384);
+}
+
+#[cfg(test)]
+fn gapless_stream() -> Vec<u8> {
+  // An Info frame with a LAME tag giving a delay of 576 samples and 1000
+  // samples of padding, followed by four silent mono Layer III frames.
+  let mut data = vec![0u8; 417 * 5];
+
+  for frame in 0..5 {
+    data[frame * 417..frame * 417 + 4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+  }
+
+  data[21..25].copy_from_slice(b"Info");
+  data[25..33].copy_from_slice(&[0, 0, 0, 0x1, 0, 0, 0, 4]);
+  data[33..37].copy_from_slice(b"LAME");
+  data[54..57].copy_from_slice(&[0x24, 0x03, 0xE8]);
+
+  data
+}
+
+#[test]
+fn test_gapless() {
+  let data = gapless_stream();
+
+  for &gapless in [true, false].iter() {
+    let mut decoder = Decoder::new(&data[..]);
+    let mut lengths = Vec::new();
+
+    decoder.set_gapless(gapless);
+
+    while let Some(mut frame) = decoder.next_frame().unwrap() {
+      lengths.push(decoder.decode_frame(&mut frame).unwrap()[0].len());
+    }
+
+    if gapless {
+      assert_eq!(lengths, vec![0, 47, 1152, 1152, 681]);
+    } else {
+      assert_eq!(lengths, vec![0, 1152, 1152, 1152, 1152]);
+    }
+  }
+
+  // A second stream put after the first is trimmed in turn, and frames
+  // that no Info frame describes are left as they are. The Info frame is
+  // only looked for where the previous stream ends.
+  let mut joined = [&data[..], &data[..]].concat();
+
+  joined.extend(&data[417..1251]);
+
+  let mut decoder = Decoder::new(&joined[..]);
+  let mut lengths = Vec::new();
+
+  while let Some(mut frame) = decoder.next_frame().unwrap() {
+    assert_eq!(frame.vbr_header.is_some(), frame.index == 0 || frame.index == 5);
+    lengths.push(decoder.decode_frame(&mut frame).unwrap()[0].len());
+  }
+
+  assert_eq!(lengths, vec![0, 47, 1152, 1152, 681, 0, 47, 1152, 1152, 681, 1152, 1152]);
+}
+
+#[test]
+fn test_skipped_frame_trimmed() {
+  // The second audio frame is protected by a CRC that fails, and a frame's
+  // worth of junk in front of it is concealed.
+  let data = gapless_stream();
+  let mut damaged = data[..834].to_vec();
+
+  damaged.extend(&[0u8; 417]);
+  damaged.extend(&data[834..]);
+  damaged[1252] = 0xFA;
+
+  let mut decoder = Decoder::new(&damaged[..]);
+  let mut lengths = Vec::new();
+
+  decoder.set_crc_policy(crc::CrcPolicy::Skip);
+
+  while let Some(mut frame) = decoder.next_frame().unwrap() {
+    lengths.push(decoder.decode_frame(&mut frame).unwrap()[0].len());
+  }
+
+  // The concealed samples are trimmed like those of the frame would be.
+  assert_eq!(lengths, vec![0, 47, 1152, 1152, 681]);
+}
+
+#[test]
+fn test_seek() {
+  // Mono Layer I frames of 672 bytes at 448 kbit/s and 32 kHz, allocating
+  // four bits to every subband, with scale factors and samples from a
+  // pseudo random sequence.
+  let mut data = vec![0u8; 672 * 20];
+  let mut seed = 1u32;
+
+  for frame in 0..20 {
+    let frame = &mut data[frame * 672..(frame + 1) * 672];
+
+    frame[..4].copy_from_slice(&[0xFF, 0xFF, 0xE8, 0xC0]);
+
+    for byte in frame[4..20].iter_mut() {
+      *byte = 0x33;
+    }
+
+    for byte in frame[20..236].iter_mut() {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      *byte = (seed >> 16) as u8;
+    }
+  }
+
+  let decode = |decoder: &mut Decoder<io::Cursor<&Vec<u8>>>| -> Vec<f64> {
+    let mut pcm = Vec::new();
+
+    while let Some(mut frame) = decoder.next_frame().unwrap() {
+      pcm.extend(decoder.decode_frame(&mut frame).unwrap().remove(0));
+    }
+
+    pcm
+  };
+
+  let all = decode(&mut Decoder::new(io::Cursor::new(&data)));
+
+  assert_eq!(all.len(), 20 * 384);
+
+  // Seeking forwards past the frames read so far, backwards, and past the
+  // end.
+  let mut decoder = Decoder::new(io::Cursor::new(&data));
+
+  for &target in [5000, 1234, 0, 7679].iter() {
+    decoder.seek(target as u64).unwrap();
+
+    assert_eq!(decode(&mut decoder), &all[target..]);
+  }
+
+  decoder.seek(20000).unwrap();
+
+  assert!(decode(&mut decoder).is_empty());
+}
+
+#[test]
+fn test_seek_xing() {
+  // A Xing frame followed by 20 silent mono Layer III frames at 64 kbit/s
+  // and 20 at 192 kbit/s, 208 and 626 bytes long. The seek table is off, as
+  // if the bitrate were constant.
+  let mut data = vec![0u8; 417 + 20 * 208 + 20 * 626];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+  data[21..37].copy_from_slice(&[b'X', b'i', b'n', b'g', 0, 0, 0, 0x7, 0, 0, 0, 40, 0, 0, 0x42, 0xC9]);
+
+  for i in 0..100 {
+    data[37 + i] = (i * 256 / 100) as u8;
+  }
+
+  for frame in 0..40 {
+    let (offset, bitrate) = if frame < 20 { (417 + frame * 208, 0x50) } else { (4577 + (frame - 20) * 626, 0xB0) };
+
+    data[offset..offset + 4].copy_from_slice(&[0xFF, 0xFB, bitrate, 0xC4]);
+  }
+
+  let mut decoder = Decoder::new(io::Cursor::new(&data));
+
+  // Sample 30 * 1152 is in frame 31, and decoding starts 8 frames earlier
+  // at the third frame at 192 kbit/s, which the frame headers put where the
+  // table does not.
+  decoder.seek(30 * 1152).unwrap();
+
+  let frame = decoder.next_frame().unwrap().unwrap();
+
+  assert_eq!((frame.offset, frame.index), (4577 + 2 * 626, 23));
+
+  // Seeking back takes the frame from the index.
+  decoder.seek(2 * 1152).unwrap();
+
+  let frame = decoder.next_frame().unwrap().unwrap();
+
+  assert_eq!((frame.offset, frame.index), (417, 1));
+}
+
+#[test]
+fn test_seek_bitrate_change() {
+  // Mono Layer I frames at 32 kHz, one of 672 bytes at 448 kbit/s, two of
+  // 336 bytes at 224 kbit/s and 17 more of 672 bytes. Their sizes would put
+  // frame 9 where frame 8 is looked for.
+  let mut data = Vec::new();
+
+  for frame in 0..20 {
+    let (size, bitrate) = if frame == 1 || frame == 2 { (336, 0x78) } else { (672, 0xE8) };
+    let mut bytes = vec![0u8; size];
+
+    bytes[..4].copy_from_slice(&[0xFF, 0xFF, bitrate, 0xC0]);
+    data.extend(bytes);
+  }
+
+  let mut decoder = Decoder::new(io::Cursor::new(&data));
+
+  for _ in 0..4 {
+    decoder.next_frame().unwrap().unwrap();
+  }
+
+  // Sample 10 * 384 is in frame 10, and decoding starts 2 frames earlier.
+  decoder.seek(10 * 384).unwrap();
+
+  let frame = decoder.next_frame().unwrap().unwrap();
+
+  assert_eq!((frame.offset, frame.index), (7 * 672, 8));
+}
+
+#[test]
+fn test_no_frames() {
+  let data = vec![0xFFu8; 1000];
+  let mut decoder = Decoder::new(&data[..]);
+
+  match decoder.next_frame() {
+    Err(error::Error::InvalidHeader(_)) => {},
+    _ => panic!("stream without frames not reported")
+  };
+
+  assert!(decoder.next_frame().unwrap().is_none());
+  assert!(Decoder::new(&data[..0]).next_frame().unwrap().is_none());
+}
+
+#[test]
+fn test_conceal_corrupt_frame() {
+  // Allocating 15 bits to every sample needs far more than the frame holds.
+  let mut data = silent_layer1_stream(3);
+
+  for byte in data[52..68].iter_mut() {
+    *byte = 0xEE;
+  }
+
+  let mut decoder = Decoder::new(&data[..]);
+
+  decoder.set_concealment(conceal::ConcealMethod::Repeat);
+
+  let mut first = decoder.next_frame().unwrap().unwrap();
+  let mut second = decoder.next_frame().unwrap().unwrap();
+
+  decoder.decode_frame(&mut first).unwrap();
+
+  assert_eq!(decoder.decode_frame(&mut second).unwrap()[0], vec![0f64; 384]);
+  assert!(first.concealed.is_none());
+
+  match second.concealed {
+    Some(error::Error::FrameOverrun(Some(p))) => assert_eq!(p, error::Position { offset: 48, frame: 1 }),
+    _ => panic!("corrupt frame not concealed")
+  };
+}
+
+#[test]
+fn test_truncated_stream() {
+  let data = silent_layer1_stream(2);
+  let mut decoder = Decoder::new(&data[..90]);
+
+  assert!(decoder.next_frame().unwrap().is_some());
+  match decoder.next_frame() {
+    Err(error::Error::UnexpectedEof(Some(p))) => assert_eq!(p, error::Position { offset: 48, frame: 1 }),
+    _ => panic!("cut short frame not reported")
+  };
+}
+
+#[test]
+fn test_push_in_chunks() {
+  let data = silent_layer1_stream(2);
+  let mut decoder = StreamDecoder::new();
+  let mut frames = 0;
+
+  // Frames come out as soon as they are complete, however the data is cut.
+  for chunk in data.chunks(7) {
+    decoder.feed(chunk);
+
+    loop {
+      match decoder.next_frame() {
+        Next::Frame(mut f) => {
+          assert_eq!(decoder.decode_frame(&mut f).unwrap()[0].len(), 384);
+          frames += 1;
+        },
+        Next::NeedMoreData => break,
+        Next::End => panic!("stream ended before it was finished"),
+        Next::Error(e) => panic!("{}", e)
+      };
+    }
+  }
+
+  assert_eq!(frames, 2);
+
+  decoder.feed(&data[..30]);
+  decoder.finish();
+
+  match decoder.next_frame() {
+    Next::Error(error::Error::UnexpectedEof(_)) => {},
+    _ => panic!("cut short frame not reported")
+  };
+
+  // The end is reported every time once nothing is left.
+  for _ in 0..2 {
+    match decoder.next_frame() {
+      Next::End => {},
+      _ => panic!("end of stream not reported")
+    };
+  }
+}
+
+
+#[test]
+fn test_id3v2_tag() {
+  // A tag holding what looks like a frame header, pushed in two chunks.
+  let mut data = b"ID3\x03\x00\x00\x00\x00\x00\x1ATIT2\x00\x00\x00\x06\x00\x00\x00Title".to_vec();
+
+  data.extend(b"\xFF\xFF\x18\xC0\x00\x00\x00\x00\x00\x00");
+  data.extend(silent_layer1_stream(2));
+
+  let mut decoder = StreamDecoder::new();
+
+  decoder.feed(&data[..20]);
+
+  match decoder.next_frame() {
+    Next::NeedMoreData => assert!(decoder.id3v2().is_none()),
+    _ => panic!("frame parsed from a partial tag")
+  };
+
+  decoder.feed(&data[20..]);
+  decoder.finish();
+
+  match decoder.next_frame() {
+    Next::Frame(f) => assert_eq!((f.offset, f.index, f.lost_frames), (36, 0, 0)),
+    _ => panic!("no frame parsed")
+  };
+
+  assert_eq!(decoder.id3v2().unwrap().text("TIT2"), Some("Title"));
+}
+
+#[test]
+fn test_trailing_tags() {
+  // An APEv2 tag holding what looks like frames, a Lyrics3v1 tag and an
+  // ID3v1 tag after a single frame.
+  let mut data = silent_layer1_stream(1);
+
+  for &flags in [0xA0u8, 0x80].iter() {
+    data.extend(b"APETAGEX\xD0\x07\x00\x00\x36\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00");
+    data.extend(&[flags, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    if flags == 0xA0 {
+      data.extend(b"\x08\x00\x00\x00\x02\x00\x00\x00Cover\x00\xFF\xFF\x18\xC0\xFF\xFF\x18\xC0");
+    }
+  }
+
+  data.extend(b"LYRICSBEGINHello LYRICSEND");
+  data.extend(b"TAGTitle");
+  data.extend(&[0u8; 120]);
+
+  let mut decoder = Decoder::new(&data[..]);
+  let mut frames = 0;
+
+  while decoder.next_frame().unwrap().is_some() {
+    frames += 1;
+  }
+
+  assert_eq!(frames, 1);
+  assert_eq!(decoder.tags().len(), 3);
+
+  match decoder.tags()[0] {
+    tags::Tag::Ape(ref tag) => assert_eq!(tag.items, vec![("Cover".to_string(), tags::ApeValue::Binary(vec![0xFF, 0xFF, 0x18, 0xC0, 0xFF, 0xFF, 0x18, 0xC0]))]),
+    _ => panic!("APEv2 tag not found")
+  };
+
+  match decoder.tags()[2] {
+    tags::Tag::Id3v1(ref tag) => assert_eq!(tag.title, "Title"),
+    _ => panic!("ID3v1 tag not found")
+  };
+}
+
+#[test]
+fn test_ape_footer() {
+  // An APEv2 tag with a footer and no header between frames.
+  let mut data = silent_layer1_stream(2);
+
+  data.extend(b"\x0B\x00\x00\x00\x00\x00\x00\x00Artist\x00Name1\x00Name2");
+  data.extend(b"APETAGEX\xD0\x07\x00\x00\x3A\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x80");
+  data.extend(&[0u8; 8]);
+  data.extend(silent_layer1_stream(1));
+
+  let mut decoder = Decoder::new(&data[..]);
+  let mut lost_frames = Vec::new();
+
+  while let Some(frame) = decoder.next_frame().unwrap() {
+    lost_frames.push(frame.lost_frames);
+  }
+
+  assert_eq!(lost_frames, vec![0, 0, 0]);
+  assert_eq!(decoder.tags(), &[tags::Tag::Ape(tags::ApeTag {
+    version: 2000,
+    items: vec![("Artist".to_string(), tags::ApeValue::Text(vec!["Name1".to_string(), "Name2".to_string()]))]
+  })]);
+}
+
+#[test]
+fn test_seek_to_chapter() {
+  // A chapter starting 30 ms into five frames of 12 ms at 32 kHz.
+  let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x1CCHAP\x00\x00\x00\x12\x00\x00c\x00\x00\x00\x00\x1E\x00\x00\x00\x3C".to_vec();
+
+  data.extend(&[0xFF; 8]);
+  data.extend(silent_layer1_stream(5));
+
+  let mut decoder = Decoder::new(io::Cursor::new(data));
+
+  assert!(!decoder.seek_to_chapter("d").unwrap());
+  assert!(decoder.seek_to_chapter("c").unwrap());
+
+  let mut samples = 0;
+
+  while let Some(mut frame) = decoder.next_frame().unwrap() {
+    samples += decoder.decode_frame(&mut frame).unwrap()[0].len();
+  }
+
+  assert_eq!(samples, 5 * 384 - 960);
+}
@@ -0,0 +1,104 @@
+use header;
+use header::Emphasis::*;
+
+/// A first order shelving filter undoing the emphasis of a channel. Both
+/// emphases boost the treble with a zero and a pole given by two time
+/// constants, which the filter inverts through the bilinear transform.
+pub struct DeemphasisFilter {
+  emphasis: header::Emphasis,
+  samplerate: u32,
+  b0: f64,
+  b1: f64,
+  a1: f64,
+  x1: f64,
+  y1: f64
+}
+
+/// The time constants in seconds of the pole and zero of an emphasis. The
+/// corners of CCITT J.17 are at 3000 rad/s and sqrt(75) times that.
+fn time_constants(emphasis: header::Emphasis) -> Option<(f64, f64)> {
+  match emphasis {
+    Emphasis50_15 => Some((50e-6, 15e-6)),
+    EmphasisCCITT_J17 => Some((1.0 / 3000.0, 1.0 / (3000.0 * 75f64.sqrt()))),
+    _ => None
+  }
+}
+
+impl DeemphasisFilter {
+  pub fn new() -> DeemphasisFilter {
+    DeemphasisFilter {
+      emphasis: EmphasisNone, samplerate: 0, b0: 1.0, b1: 0.0, a1: 0.0, x1: 0.0, y1: 0.0
+    }
+  }
+
+  /// Filters the samples of one channel in place. The filter starts over
+  /// when the emphasis or sampling frequency change, and leaves the samples
+  /// alone without emphasis.
+  pub fn process(&mut self, emphasis: header::Emphasis, samplerate: u32, samples: &mut [f64]) {
+    if emphasis != self.emphasis || samplerate != self.samplerate {
+      self.configure(emphasis, samplerate);
+    }
+
+    for sample in samples.iter_mut() {
+      let y = self.b0 * *sample + self.b1 * self.x1 - self.a1 * self.y1;
+
+      self.x1 = *sample;
+      self.y1 = y;
+      *sample = y;
+    }
+  }
+
+  fn configure(&mut self, emphasis: header::Emphasis, samplerate: u32) {
+    self.emphasis = emphasis;
+    self.samplerate = samplerate;
+    self.x1 = 0.0;
+    self.y1 = 0.0;
+
+    let (pole, zero) = match time_constants(emphasis) {
+      Some(t) => t,
+      None => {
+        self.b0 = 1.0;
+        self.b1 = 0.0;
+        self.a1 = 0.0;
+        return;
+      }
+    };
+
+    // H(s) = (1 + s * zero) / (1 + s * pole) with s = k * (1 - z^-1) / (1 + z^-1).
+    let k = 2.0 * samplerate as f64;
+    let norm = 1.0 + k * pole;
+
+    self.b0 = (1.0 + k * zero) / norm;
+    self.b1 = (1.0 - k * zero) / norm;
+    self.a1 = (1.0 - k * pole) / norm;
+  }
+}
+
+#[test]
+fn test_no_emphasis() {
+  let mut filter = DeemphasisFilter::new();
+  let mut samples = [0.5, -0.25, 1.0, 0.0];
+
+  filter.process(EmphasisNone, 44100, &mut samples);
+
+  assert_eq!(samples, [0.5, -0.25, 1.0, 0.0]);
+}
+
+#[test]
+fn test_50_15_gain() {
+  // Direct current passes, and the highest frequency is attenuated by the
+  // ratio of the time constants, 15/50.
+  let mut filter = DeemphasisFilter::new();
+  let mut dc = [1f64; 4096];
+
+  filter.process(Emphasis50_15, 44100, &mut dc);
+
+  assert!((dc[4095] - 1.0).abs() < 1e-9);
+
+  let mut filter = DeemphasisFilter::new();
+  let mut nyquist: Vec<f64> = (0..4096).map(|i| if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+
+  filter.process(Emphasis50_15, 44100, &mut nyquist);
+
+  assert!((nyquist[4095].abs() - 0.3).abs() < 1e-9);
+}
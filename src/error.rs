@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// Where in the stream an error happened: the byte offset of the frame from
+/// the start of the stream, and its index among the frames.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Position {
+  pub offset: u64,
+  pub frame: u64
+}
+
+/// The errors of the decoder. Most of them are found while decoding a frame
+/// before its position is known, so the position is only filled in by the
+/// decoder on the way out.
+#[derive(Debug)]
+pub enum Error {
+  /// Reading the source failed.
+  Io(io::Error),
+  /// The stream ends in the middle of a frame.
+  UnexpectedEof(Option<Position>),
+  /// No frame header was found where one was expected.
+  InvalidHeader(Option<Position>),
+  /// A field holds a value reserved by the standard.
+  ReservedField(&'static str, Option<Position>),
+  /// The frame fails its CRC check.
+  CrcMismatch(Option<Position>),
+  /// Decoding the frame reads past the end of its data.
+  FrameOverrun(Option<Position>),
+  /// The frame is of a layer the decoder does not handle.
+  UnsupportedLayer(Option<Position>),
+  /// The frame data makes no sense, for the given reason.
+  InvalidData(&'static str, Option<Position>)
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+  /// The position of the error, when it is known.
+  pub fn position(&self) -> Option<Position> {
+    match *self {
+      Error::Io(_) => None,
+      Error::UnexpectedEof(p) | Error::InvalidHeader(p) | Error::CrcMismatch(p) |
+      Error::FrameOverrun(p) | Error::UnsupportedLayer(p) => p,
+      Error::ReservedField(_, p) | Error::InvalidData(_, p) => p
+    }
+  }
+
+  /// Places the error in the frame at `offset` with index `frame`, unless it
+  /// was placed already.
+  pub fn at(self, offset: u64, frame: u64) -> Error {
+    if self.position().is_some() {
+      return self;
+    }
+
+    let p = Some(Position { offset, frame });
+
+    match self {
+      Error::Io(e) => Error::Io(e),
+      Error::UnexpectedEof(_) => Error::UnexpectedEof(p),
+      Error::InvalidHeader(_) => Error::InvalidHeader(p),
+      Error::ReservedField(field, _) => Error::ReservedField(field, p),
+      Error::CrcMismatch(_) => Error::CrcMismatch(p),
+      Error::FrameOverrun(_) => Error::FrameOverrun(p),
+      Error::UnsupportedLayer(_) => Error::UnsupportedLayer(p),
+      Error::InvalidData(reason, _) => Error::InvalidData(reason, p)
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let result = match *self {
+      Error::Io(ref e) => write!(f, "{}", e),
+      Error::UnexpectedEof(_) => write!(f, "the stream ends in the middle of a frame"),
+      Error::InvalidHeader(_) => write!(f, "no frame header found"),
+      Error::ReservedField(field, _) => write!(f, "reserved value in {}", field),
+      Error::CrcMismatch(_) => write!(f, "CRC mismatch"),
+      Error::FrameOverrun(_) => write!(f, "the frame data runs past the end of the frame"),
+      Error::UnsupportedLayer(_) => write!(f, "unsupported layer"),
+      Error::InvalidData(reason, _) => write!(f, "{}", reason)
+    };
+
+    match (result, self.position()) {
+      (Ok(_), Some(p)) => write!(f, " at byte {} (frame {})", p.offset, p.frame),
+      (result, _) => result
+    }
+  }
+}
+
+impl error::Error for Error {}
+
+#[test]
+fn test_position() {
+  let error = Error::FrameOverrun(None).at(480, 10);
+
+  assert_eq!(error.position(), Some(Position { offset: 480, frame: 10 }));
+  assert_eq!(error.at(0, 0).position(), Some(Position { offset: 480, frame: 10 }));
+  assert_eq!(format!("{}", Error::ReservedField("table_select", None).at(96, 2)), "reserved value in table_select at byte 96 (frame 2)");
+}
@@ -1,21 +1,280 @@
-use std::io;
-
-use peeker;
 use header;
+use crc;
+use error;
+use tags;
+use vbr;
 
 #[derive(Debug)]
 pub struct MpegFrame {
-  pub header: header::Header
+  pub header: header::Header,
+  pub crc: crc::CrcStatus,
+  /// Why the frame was concealed rather than decoded, once it is decoded.
+  pub concealed: Option<error::Error>,
+  /// The Xing, Info or VBRI header the frame holds instead of audio, which
+  /// is only looked for in the first frame of a stream.
+  pub vbr_header: Option<vbr::VbrHeader>,
+  /// The byte offset of the frame in the stream and its index among the
+  /// frames, set by the decoder.
+  pub offset: u64,
+  pub index: u64,
+  /// The number of frames that went missing to damage in the stream right
+  /// before this one.
+  pub lost_frames: usize,
+  data: Vec<u8>,
+  ancillary: Vec<u8>
 }
 
 impl MpegFrame {
-  pub fn read_from(reader: &mut peeker::Peeker) -> io::Result<Option<MpegFrame>> {
-    return match header::Header::read_from(reader) {
-      Ok(h) => match h {
-        Some(h) => Ok(Some(MpegFrame { header: h })),
-        None => Ok(None)
-      },
-      Err(e) => Err(e)
+  /// Takes the frame of `frame_header` from `data`, which holds exactly its
+  /// `frame_size` bytes.
+  fn new(frame_header: header::Header, data: &[u8]) -> MpegFrame {
+    let crc = crc::check_frame(&frame_header, data);
+
+    MpegFrame {
+      header: frame_header,
+      crc,
+      vbr_header: None,
+      concealed: None,
+      offset: 0,
+      index: 0,
+      lost_frames: 0,
+      data: data.to_vec(),
+      ancillary: Vec::new()
+    }
+  }
+
+  /// Looks for a Xing, Info or VBRI header in the frame.
+  pub fn read_vbr_header(&mut self) {
+    self.vbr_header = vbr::find_vbr_header(&self.header, &self.data);
+  }
+
+  /// The size of the frame in bytes, header included.
+  pub fn len(&self) -> usize {
+    self.data.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.data.is_empty()
+  }
+
+  /// The bytes of the frame following the header and CRC check word, which
+  /// the layer decoders work on.
+  pub fn payload(&self) -> &[u8] {
+    &self.data[self.header.length()..]
+  }
+
+  /// The ancillary data following the samples of a Layer I or II frame,
+  /// available once the frame is decoded.
+  pub fn ancillary(&self) -> &[u8] {
+    &self.ancillary
+  }
+
+  pub fn set_ancillary(&mut self, ancillary: Vec<u8>) {
+    self.ancillary = ancillary;
+  }
+}
+
+/// What `FrameReader::parse` found at the start of the data.
+pub enum Parsed {
+  Frame(MpegFrame),
+  /// The frame goes on past the data, or the data is too short to tell
+  /// whether a frame starts it. When the data is all there is, the frame is
+  /// cut short.
+  NeedMoreData,
+  /// The data starts with that many bytes that belong to no frame, which
+  /// are to be dropped before parsing again.
+  Junk(usize),
+  /// The data starts with a tag of that many bytes, which are to be dropped
+  /// before parsing again, with its contents when they can be read.
+  Tag(usize, Option<tags::Tag>)
+}
+
+/// What `FrameReader::check` made of a possible frame.
+enum Candidate {
+  /// A frame of that many bytes.
+  Frame(header::Header, usize),
+  NeedMoreData,
+  Invalid
+}
+
+/// Parses the frames of one stream, carrying what has been learned about the
+/// stream from one frame to the next.
+pub struct FrameReader {
+  free_format_size: Option<u32>,
+  /// The header of the last frame, as long as the reader is in sync.
+  last: Option<header::Header>
+}
+
+impl Default for FrameReader {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl FrameReader {
+  pub fn new() -> FrameReader {
+    FrameReader { free_format_size: None, last: None }
+  }
+
+  /// Forgets the frames parsed so far, as when the stream is entered
+  /// somewhere else, so that the next frame has to be confirmed again.
+  pub fn resync(&mut self) {
+    self.last = None;
+  }
+
+  /// Parses the frame at the start of `data`, where `end` says that nothing
+  /// follows `data` in the stream. The frame size of a free format stream is
+  /// measured at its first frame and kept for the rest of the stream.
+  ///
+  /// Bytes are skipped one at a time until a tag or a header without
+  /// reserved fields is found. Out of sync, the header is only taken when
+  /// the next frame starts with a header consistent with it or with a tag,
+  /// or when its frame ends the stream. In sync, a header consistent with
+  /// the last one is enough.
+  pub fn parse(&mut self, data: &[u8], end: bool) -> error::Result<Parsed> {
+    let mut offset = 0;
+
+    while offset + 4 <= data.len() {
+      match tags::find_tag(&data[offset..], end) {
+        tags::Found::Tag(size) => return Ok(if offset > 0 { Parsed::Junk(offset) } else { Parsed::Tag(size, tags::parse(&data[..size])) }),
+        tags::Found::NeedMoreData => return Ok(if offset > 0 { Parsed::Junk(offset) } else { Parsed::NeedMoreData }),
+        tags::Found::Nothing => {}
+      };
+
+      match self.check(&data[offset..], end) {
+        Candidate::Frame(h, size) => return Ok(if offset > 0 { Parsed::Junk(offset) } else { Parsed::Frame(MpegFrame::new(h, &data[..size])) }),
+        Candidate::NeedMoreData => return Ok(if offset > 0 { Parsed::Junk(offset) } else { Parsed::NeedMoreData }),
+        Candidate::Invalid => {
+          self.last = None;
+          offset += 1;
+        }
+      };
+    }
+
+    if end && offset < data.len() {
+      return Ok(Parsed::Junk(data.len()));
     }
+
+    Ok(if offset > 0 { Parsed::Junk(offset) } else { Parsed::NeedMoreData })
+  }
+
+  fn check(&mut self, data: &[u8], end: bool) -> Candidate {
+    let mut frame_header = match header::Header::from_bytes(data) {
+      Some(h) if h.is_valid() => h,
+      _ => return Candidate::Invalid
+    };
+
+    if frame_header.free_format {
+      if self.free_format_size.is_none() {
+        if data.len() < header::FREE_FORMAT_LOOKAHEAD && !end {
+          return Candidate::NeedMoreData;
+        }
+
+        self.free_format_size = header::find_free_format_size(data, &frame_header);
+      }
+
+      match self.free_format_size {
+        Some(size) => frame_header.set_free_format_size(size),
+        None => return Candidate::Invalid
+      };
+    }
+
+    let frame_size = match frame_header.frame_size() {
+      Some(s) => s as usize,
+      None => return Candidate::Invalid
+    };
+
+    if frame_size < frame_header.length() {
+      return Candidate::Invalid;
+    }
+
+    let in_sync = match self.last {
+      Some(ref last) => frame_header.is_consistent_with(last),
+      None => false
+    };
+
+    if in_sync {
+      if data.len() < frame_size {
+        return Candidate::NeedMoreData;
+      }
+    } else if data.len() >= frame_size + 4 {
+      match header::Header::from_bytes(&data[frame_size..]) {
+        Some(ref h) if h.is_valid() && h.is_consistent_with(&frame_header) => {},
+        _ if tags::starts_tag(&data[frame_size..]) => {},
+        _ => return Candidate::Invalid
+      };
+    } else if !end {
+      return Candidate::NeedMoreData;
+    } else if data.len() != frame_size {
+      return Candidate::Invalid;
+    }
+
+    self.last = Some(frame_header.clone());
+
+    Candidate::Frame(frame_header, frame_size)
+  }
+}
+
+#[test]
+fn test_parse() {
+  // A 48 byte Layer I frame followed by the start of the next one.
+  let mut data = vec![0u8; 60];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFF, 0x18, 0xC0]);
+  data[48..52].copy_from_slice(&[0xFF, 0xFF, 0x18, 0xC0]);
+
+  let mut frames = FrameReader::new();
+
+  match frames.parse(&data, false).unwrap() {
+    Parsed::Frame(f) => {
+      assert_eq!(f.len(), 48);
+      assert_eq!(f.payload().len(), 44);
+    },
+    _ => panic!("no frame parsed")
+  };
+
+  match frames.parse(&data[48..], false).unwrap() {
+    Parsed::NeedMoreData => {},
+    _ => panic!("frame parsed from partial data")
+  };
+
+  match frames.parse(&data[52..], true).unwrap() {
+    Parsed::Junk(8) => {},
+    _ => panic!("frame parsed without a header")
+  };
+}
+
+#[test]
+fn test_resync() {
+  // Junk with a stray sync word, then two 48 byte Layer I frames.
+  let mut data = vec![0u8; 10];
+
+  data[3..7].copy_from_slice(&[0xFF, 0xFF, 0x18, 0xC0]);
+
+  for _ in 0..2 {
+    let mut frame = vec![0u8; 48];
+
+    frame[..4].copy_from_slice(&[0xFF, 0xFF, 0x18, 0xC0]);
+    data.extend(frame);
   }
+
+  let mut frames = FrameReader::new();
+
+  match frames.parse(&data, true).unwrap() {
+    Parsed::Junk(10) => {},
+    _ => panic!("junk not skipped")
+  };
+
+  match frames.parse(&data[10..], true).unwrap() {
+    Parsed::Frame(f) => assert_eq!(f.len(), 48),
+    _ => panic!("no frame parsed")
+  };
+
+  // A header with a reserved sampling frequency is not taken for a frame.
+  data[58..62].copy_from_slice(&[0xFF, 0xFF, 0x1C, 0xC0]);
+
+  match frames.parse(&data[58..], true).unwrap() {
+    Parsed::Junk(48) => {},
+    _ => panic!("invalid header taken for a frame")
+  };
 }
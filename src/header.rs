@@ -1,13 +1,13 @@
 #![allow(dead_code)]
 #![allow(non_upper_case_globals)]
 
-use std::io;
-use std::io::SeekFrom;
-use peeker::Peeker;
 use self::MpegVersion::*;
 use self::MpegLayer::*;
+use self::Emphasis::*;
 
-#[derive(Debug,Clone,Copy)]
+use error;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum MpegVersion {
   MPEG1_0,
@@ -24,6 +24,18 @@ pub enum MpegLayer {
   LayerReserved
 }
 
+/// The emphasis the audio was recorded with, which the decoder can undo.
+/// Emphasis 50/15 is the 50/15 microseconds emphasis, and CCITT J.17 that of
+/// telephony.
+#[derive(Debug,Clone,Copy,PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Emphasis {
+  EmphasisNone,
+  Emphasis50_15,
+  EmphasisReserved,
+  EmphasisCCITT_J17
+}
+
 fn new_mpeg_version(i: u32) -> MpegVersion {
   match i {
     0x0 => MPEG2_5, 0x2 => MPEG2_0, 0x3 => MPEG1_0, _ => MPEGReserved
@@ -36,6 +48,12 @@ fn new_mpeg_layer(i: u32) -> MpegLayer {
   }
 }
 
+fn new_mpeg_emphasis(i: u32) -> Emphasis {
+  match i {
+    0x0 => EmphasisNone, 0x1 => Emphasis50_15, 0x3 => EmphasisCCITT_J17, _ => EmphasisReserved
+  }
+}
+
 fn new_mpeg_bitrate(v: MpegVersion, l: MpegLayer, bits: u32) -> Option<u32> {
   if bits == 0 {
     return None; /* Free bitrate */
@@ -175,12 +193,29 @@ bitflags! {
   }
 }
 
-#[derive(Debug)]
+/// The largest free format frame looked for, which is more than a Layer III
+/// frame at 640 kbit/s and 32 kHz.
+const MAX_FREE_FORMAT_SIZE: usize = 4096;
+
+/// The number of frames that have to follow at the same distance for a free
+/// format frame size to be trusted.
+const FREE_FORMAT_FRAMES: usize = 3;
+
+/// The number of bytes from the start of a free format frame needed to find
+/// its size, unless the stream ends before.
+pub const FREE_FORMAT_LOOKAHEAD: usize = FREE_FORMAT_FRAMES * MAX_FREE_FORMAT_SIZE + 4;
+
+#[derive(Debug,Clone)]
 pub struct Header {
   pub version: MpegVersion,
   pub layer: MpegLayer,
   pub crc: bool,
+  /// The bitrate in kbit/s. For free format streams this is inferred from
+  /// the frame size once it is known.
   pub bitrate: Option<u32>,
+  pub free_format: bool,
+  /// The size of free format frames without padding.
+  pub free_format_size: Option<u32>,
   pub samplerate: Option<u32>,
   pub padding: bool,
   pub private: bool,
@@ -188,30 +223,20 @@ pub struct Header {
   pub channel_mode_extension: u32,
   pub copyright: bool,
   pub original: bool,
-  pub emphasis: u32
+  pub emphasis: Emphasis
 }
 
 impl Header {
-  pub fn read_from(reader: &mut Peeker) -> io::Result<Option<Header>> {
-    return match reader.peek_be_u32() {
-      Ok(v) => match Header::from_binary(&BinaryHeader { bits: v }) {
-        Some(s) => {
-          match
-            reader.seek(if s.crc {
-                          SeekFrom::Current(6)
-                        } else {
-                          SeekFrom::Current(4)
-                        })
-          {
-            Ok(_res) => {}, Err(e) => return Err(e)
-          };
-
-          Ok(Some(s))
-        },
-        None => Ok(None)
-      },
-      Err(e) => Err(e)
-    }
+  /// Parses the header at the start of `data`, which holds at least 4 bytes.
+  pub fn from_bytes(data: &[u8]) -> Option<Header> {
+    let bits = ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32;
+
+    Header::from_binary(&BinaryHeader { bits })
+  }
+
+  /// The length of the header including the CRC check word.
+  pub fn length(&self) -> usize {
+    if self.crc { 6 } else { 4 }
   }
 
   pub fn from_binary(bin: &BinaryHeader) -> Option<Header> {
@@ -221,7 +246,8 @@ impl Header {
 
     let version = new_mpeg_version((bin.bits & Version.bits) >> 19);
     let layer = new_mpeg_layer((bin.bits & Layer.bits) >> 17);
-    let bitrate = new_mpeg_bitrate(version, layer, (bin.bits & Bitrate.bits) >> 12);
+    let bitrate_index = (bin.bits & Bitrate.bits) >> 12;
+    let bitrate = new_mpeg_bitrate(version, layer, bitrate_index);
     let samplerate = new_mpeg_samplerate(version, (bin.bits & Samplerate.bits) >> 10);
 
     return Some(Header {
@@ -229,6 +255,8 @@ impl Header {
       layer: layer,
       crc: !bin.contains(CRC),
       bitrate: bitrate,
+      free_format: bitrate_index == 0,
+      free_format_size: None,
       samplerate: samplerate,
       padding: bin.contains(Padding),
       private: bin.contains(Private),
@@ -236,10 +264,53 @@ impl Header {
       channel_mode_extension: (bin.bits & ChannelModeExtension.bits) >> 4,
       copyright: bin.contains(Copyright),
       original: bin.contains(Original),
-      emphasis: bin.bits & Original.bits
+      emphasis: new_mpeg_emphasis(bin.bits & Emphasis.bits)
     });
   }
 
+  /// Checks that none of the fields hold a reserved value, bitrate index 15
+  /// included.
+  pub fn check(&self) -> error::Result<()> {
+    if self.version == MPEGReserved {
+      return Err(error::Error::ReservedField("version", None));
+    }
+
+    if self.layer == LayerReserved {
+      return Err(error::Error::ReservedField("layer", None));
+    }
+
+    if self.bitrate.is_none() && !self.free_format {
+      return Err(error::Error::ReservedField("bitrate_index", None));
+    }
+
+    if self.samplerate.is_none() {
+      return Err(error::Error::ReservedField("sampling_frequency", None));
+    }
+
+    Ok(())
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.check().is_ok()
+  }
+
+  /// Whether `other` can be a frame of the same stream, which keeps its
+  /// version, layer, sampling frequency and free format.
+  pub fn is_consistent_with(&self, other: &Header) -> bool {
+    self.version == other.version && self.layer == other.layer &&
+      self.samplerate == other.samplerate && self.free_format == other.free_format
+  }
+
+  pub fn num_channels(&self) -> usize {
+    if self.channel_mode != 3 { 2 } else { 1 }
+  }
+
+  /// The first subband coded in intensity stereo in Layers I and II, or 32
+  /// when the frame is not joint stereo.
+  pub fn intensity_bound(&self) -> usize {
+    if self.channel_mode == 1 { 4 + 4 * self.channel_mode_extension as usize } else { 32 }
+  }
+
   pub fn slot_size(&self) -> u32 {
     return if self.layer == LayerI { 4 } else { 1 };
   }
@@ -248,14 +319,144 @@ impl Header {
     return new_mpeg_frame_samples(self.version, self.layer);
   }
 
+  /// Sets the size of the frames of a free format stream, which also gives
+  /// its bitrate.
+  pub fn set_free_format_size(&mut self, size: u32) {
+    self.free_format_size = Some(size);
+
+    self.bitrate = match (self.samplerate, self.frame_samples()) {
+      (Some(s), Some(f)) => Some(((size as u64 * s as u64 * 8 + f as u64 * 500) / (f as u64 * 1000)) as u32),
+      _ => None
+    };
+  }
+
   pub fn frame_size(&self) -> Option<u32> {
+    if let Some(v) = self.free_format_size { return Some(v + if self.padding { self.slot_size() } else { 0 }) };
+
     let b = match self.bitrate { Some(v) => v as f64, None => return None };
     let s = match self.samplerate { Some(v) => v as f64, None => return None };
     let f = match self.frame_samples() { Some(v) => v as f64, None => return None };
 
+    // Frames are made of whole slots, which are 4 bytes in Layer I.
     let bps = 1000.0 * f as f64 / 8.0;
-    let size = bps * b / s + if self.padding { self.slot_size() as f64 } else { 0.0 };
+    let slot_size = self.slot_size();
+    let slots = (bps * b / s / slot_size as f64) as u32 + if self.padding { 1 } else { 0 };
+
+    Some(slots * slot_size)
+  }
+}
+
+/// Measures the frame size of the free format stream whose frame described
+/// by `header` starts `data`. The size is the distance to the next sync word
+/// that starts a matching free format header, as long as the frames after it
+/// follow at that same size. `data` should hold `FREE_FORMAT_LOOKAHEAD` bytes
+/// unless the stream ends before.
+pub fn find_free_format_size(data: &[u8], header: &Header) -> Option<u32> {
+  let len = if data.len() < FREE_FORMAT_LOOKAHEAD { data.len() } else { FREE_FORMAT_LOOKAHEAD };
+
+  if len < 4 {
+    return None;
+  }
+
+  let word = |i: usize| -> u32 {
+    ((data[i] as u32) << 24) | ((data[i + 1] as u32) << 16) | ((data[i + 2] as u32) << 8) | data[i + 3] as u32
+  };
 
-    return Some(size as u32);
+  // The following frames have to agree on everything but the CRC, padding
+  // and channel fields, and be free format as well.
+  let first = word(0);
+  let matches = |v: u32| -> bool {
+    v & 0xfffe0c00 == first & 0xfffe0c00 && v & Bitrate.bits == 0
+  };
+
+  let padding = |v: u32| -> usize {
+    if v & Padding.bits != 0 { header.slot_size() as usize } else { 0 }
+  };
+
+  for distance in 4..MAX_FREE_FORMAT_SIZE + padding(first) {
+    if distance + 4 > len {
+      break;
+    }
+
+    if !matches(word(distance)) || distance < padding(first) {
+      continue;
+    }
+
+    let size = distance - padding(first);
+    let mut next = distance;
+    let mut frames = 1;
+
+    // Each following frame is padded on its own.
+    while frames < FREE_FORMAT_FRAMES && next + 4 <= len && matches(word(next)) {
+      next += size + padding(word(next));
+      frames += 1;
+    }
+
+    // Running out of data vouches for the size like finding another frame.
+    if frames == FREE_FORMAT_FRAMES || next + 4 > len {
+      return Some(size as u32);
+    }
   }
+
+  None
+}
+
+#[test]
+fn test_free_format_size() {
+  // Four mono Layer III frames of 500 bytes, the second one padded, with a
+  // stray sync word inside the first.
+  let mut data = Vec::new();
+
+  for i in 0..4 {
+    let mut frame = vec![0u8; if i == 1 { 501 } else { 500 }];
+
+    frame[..4].copy_from_slice(&[0xff, 0xfb, if i == 1 { 0x02 } else { 0x00 }, 0xc0]);
+    data.extend(frame);
+  }
+
+  data[100..104].copy_from_slice(&[0xff, 0xfb, 0x00, 0xc0]);
+
+  let mut header = Header::from_bytes(&data).unwrap();
+
+  assert!(header.free_format);
+  assert_eq!(header.frame_size(), None);
+  assert_eq!(find_free_format_size(&data, &header), Some(500));
+
+  header.set_free_format_size(500);
+
+  assert_eq!(header.frame_size(), Some(500));
+  assert_eq!(header.bitrate, Some(153));
+}
+
+#[test]
+fn test_emphasis() {
+  let emphasis = |bits: u32| -> Emphasis {
+    return Header::from_binary(&BinaryHeader::from_bits_truncate(0xFFFB9064 | bits)).unwrap().emphasis;
+  };
+
+  // The original bit (0x4) is set in all of them.
+  assert_eq!(emphasis(0), EmphasisNone);
+  assert_eq!(emphasis(1), Emphasis50_15);
+  assert_eq!(emphasis(2), EmphasisReserved);
+  assert_eq!(emphasis(3), EmphasisCCITT_J17);
+}
+
+
+#[test]
+fn test_is_valid() {
+  let valid = |bits: u32| -> bool {
+    return Header::from_binary(&BinaryHeader::from_bits_truncate(bits)).unwrap().is_valid();
+  };
+
+  assert!(valid(0xFFFB9064));
+  assert!(valid(0xFFFB0064));
+  assert!(!valid(0xFFEB9064)); // Reserved version
+  assert!(!valid(0xFFF99064)); // Reserved layer
+  assert!(!valid(0xFFFBF064)); // Bitrate index 15
+  assert!(!valid(0xFFFB9C64)); // Reserved sampling frequency
+
+  match Header::from_binary(&BinaryHeader::from_bits_truncate(0xFFF99064)).unwrap().check() {
+    Err(error::Error::ReservedField("layer", None)) => {},
+    _ => panic!("reserved layer not reported")
+  };
 }
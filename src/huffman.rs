@@ -0,0 +1,610 @@
+
+use bitreader;
+use error;
+
+static TABLE_1_CODES: [u32; 4] = [
+  0x0001, 0x0001, 0x0001, 0x0000
+];
+
+static TABLE_1_LENGTHS: [u8; 4] = [
+   1,  3,  2,  3
+];
+
+static TABLE_2_CODES: [u32; 9] = [
+  0x0001, 0x0002, 0x0001, 0x0003, 0x0001, 0x0001, 0x0003, 0x0002,
+  0x0000
+];
+
+static TABLE_2_LENGTHS: [u8; 9] = [
+   1,  3,  6,  3,  3,  5,  5,  5,  6
+];
+
+static TABLE_3_CODES: [u32; 9] = [
+  0x0003, 0x0002, 0x0001, 0x0001, 0x0001, 0x0001, 0x0003, 0x0002,
+  0x0000
+];
+
+static TABLE_3_LENGTHS: [u8; 9] = [
+   2,  2,  6,  3,  2,  5,  5,  5,  6
+];
+
+static TABLE_5_CODES: [u32; 16] = [
+  0x0001, 0x0002, 0x0006, 0x0005, 0x0003, 0x0001, 0x0004, 0x0004,
+  0x0007, 0x0005, 0x0007, 0x0001, 0x0006, 0x0001, 0x0001, 0x0000
+];
+
+static TABLE_5_LENGTHS: [u8; 16] = [
+   1,  3,  6,  7,  3,  3,  6,  7,  6,  6,  7,  8,  7,  6,  7,  8
+];
+
+static TABLE_6_CODES: [u32; 16] = [
+  0x0007, 0x0003, 0x0005, 0x0001, 0x0006, 0x0002, 0x0003, 0x0002,
+  0x0005, 0x0004, 0x0004, 0x0001, 0x0003, 0x0003, 0x0002, 0x0000
+];
+
+static TABLE_6_LENGTHS: [u8; 16] = [
+   3,  3,  5,  7,  3,  2,  4,  5,  4,  4,  5,  6,  6,  5,  6,  7
+];
+
+static TABLE_7_CODES: [u32; 36] = [
+  0x0001, 0x0002, 0x000a, 0x0013, 0x0010, 0x000a, 0x0003, 0x0003,
+  0x0007, 0x000a, 0x0005, 0x0003, 0x000b, 0x0004, 0x000d, 0x0011,
+  0x0008, 0x0004, 0x000c, 0x000b, 0x0012, 0x000f, 0x000b, 0x0002,
+  0x0007, 0x0006, 0x0009, 0x000e, 0x0003, 0x0001, 0x0006, 0x0004,
+  0x0005, 0x0003, 0x0002, 0x0000
+];
+
+static TABLE_7_LENGTHS: [u8; 36] = [
+   1,  3,  6,  8,  8,  9,  3,  4,  6,  7,  7,  8,  6,  5,  7,  8,
+   8,  9,  7,  7,  8,  9,  9,  9,  7,  7,  8,  9,  9, 10,  8,  8,
+   9, 10, 10, 10
+];
+
+static TABLE_8_CODES: [u32; 36] = [
+  0x0003, 0x0004, 0x0006, 0x0012, 0x000c, 0x0005, 0x0005, 0x0001,
+  0x0002, 0x0010, 0x0009, 0x0003, 0x0007, 0x0003, 0x0005, 0x000e,
+  0x0007, 0x0003, 0x0013, 0x0011, 0x000f, 0x000d, 0x000a, 0x0004,
+  0x000d, 0x0005, 0x0008, 0x000b, 0x0005, 0x0001, 0x000c, 0x0004,
+  0x0004, 0x0001, 0x0001, 0x0000
+];
+
+static TABLE_8_LENGTHS: [u8; 36] = [
+   2,  3,  6,  8,  8,  9,  3,  2,  4,  8,  8,  8,  6,  4,  6,  8,
+   8,  9,  8,  8,  8,  9,  9, 10,  8,  7,  8,  9, 10, 10,  9,  8,
+   9,  9, 11, 11
+];
+
+static TABLE_9_CODES: [u32; 36] = [
+  0x0007, 0x0005, 0x0009, 0x000e, 0x000f, 0x0007, 0x0006, 0x0004,
+  0x0005, 0x0005, 0x0006, 0x0007, 0x0007, 0x0006, 0x0008, 0x0008,
+  0x0008, 0x0005, 0x000f, 0x0006, 0x0009, 0x000a, 0x0005, 0x0001,
+  0x000b, 0x0007, 0x0009, 0x0006, 0x0004, 0x0001, 0x000e, 0x0004,
+  0x0006, 0x0002, 0x0006, 0x0000
+];
+
+static TABLE_9_LENGTHS: [u8; 36] = [
+   3,  3,  5,  6,  8,  9,  3,  3,  4,  5,  6,  8,  4,  4,  5,  6,
+   7,  8,  6,  5,  6,  7,  7,  8,  7,  6,  7,  7,  8,  9,  8,  7,
+   8,  8,  9,  9
+];
+
+static TABLE_10_CODES: [u32; 64] = [
+  0x0001, 0x0002, 0x000a, 0x0017, 0x0023, 0x001e, 0x000c, 0x0011,
+  0x0003, 0x0003, 0x0008, 0x000c, 0x0012, 0x0015, 0x000c, 0x0007,
+  0x000b, 0x0009, 0x000f, 0x0015, 0x0020, 0x0028, 0x0013, 0x0006,
+  0x000e, 0x000d, 0x0016, 0x0022, 0x002e, 0x0017, 0x0012, 0x0007,
+  0x0014, 0x0013, 0x0021, 0x002f, 0x001b, 0x0016, 0x0009, 0x0003,
+  0x001f, 0x0016, 0x0029, 0x001a, 0x0015, 0x0014, 0x0005, 0x0003,
+  0x000e, 0x000d, 0x000a, 0x000b, 0x0010, 0x0006, 0x0005, 0x0001,
+  0x0009, 0x0008, 0x0007, 0x0008, 0x0004, 0x0004, 0x0002, 0x0000
+];
+
+static TABLE_10_LENGTHS: [u8; 64] = [
+   1,  3,  6,  8,  9,  9,  9, 10,  3,  4,  6,  7,  8,  9,  8,  8,
+   6,  6,  7,  8,  9, 10,  9,  9,  7,  7,  8,  9, 10, 10,  9, 10,
+   8,  8,  9, 10, 10, 10, 10, 10,  9,  9, 10, 10, 11, 11, 10, 11,
+   8,  8,  9, 10, 10, 10, 11, 11,  9,  8,  9, 10, 10, 11, 11, 11
+];
+
+static TABLE_11_CODES: [u32; 64] = [
+  0x0003, 0x0004, 0x000a, 0x0018, 0x0022, 0x0021, 0x0015, 0x000f,
+  0x0005, 0x0003, 0x0004, 0x000a, 0x0020, 0x0011, 0x000b, 0x000a,
+  0x000b, 0x0007, 0x000d, 0x0012, 0x001e, 0x001f, 0x0014, 0x0005,
+  0x0019, 0x000b, 0x0013, 0x003b, 0x001b, 0x0012, 0x000c, 0x0005,
+  0x0023, 0x0021, 0x001f, 0x003a, 0x001e, 0x0010, 0x0007, 0x0005,
+  0x001c, 0x001a, 0x0020, 0x0013, 0x0011, 0x000f, 0x0008, 0x000e,
+  0x000e, 0x000c, 0x0009, 0x000d, 0x000e, 0x0009, 0x0004, 0x0001,
+  0x000b, 0x0004, 0x0006, 0x0006, 0x0006, 0x0003, 0x0002, 0x0000
+];
+
+static TABLE_11_LENGTHS: [u8; 64] = [
+   2,  3,  5,  7,  8,  9,  8,  9,  3,  3,  4,  6,  8,  8,  7,  8,
+   5,  5,  6,  7,  8,  9,  8,  8,  7,  6,  7,  9,  8, 10,  8,  9,
+   8,  8,  8,  9,  9, 10,  9, 10,  8,  8,  9, 10, 10, 11, 10, 11,
+   8,  7,  7,  8,  9, 10, 10, 10,  8,  7,  8,  9, 10, 10, 10, 10
+];
+
+static TABLE_12_CODES: [u32; 64] = [
+  0x0009, 0x0006, 0x0010, 0x0021, 0x0029, 0x0027, 0x0026, 0x001a,
+  0x0007, 0x0005, 0x0006, 0x0009, 0x0017, 0x0010, 0x001a, 0x000b,
+  0x0011, 0x0007, 0x000b, 0x000e, 0x0015, 0x001e, 0x000a, 0x0007,
+  0x0011, 0x000a, 0x000f, 0x000c, 0x0012, 0x001c, 0x000e, 0x0005,
+  0x0020, 0x000d, 0x0016, 0x0013, 0x0012, 0x0010, 0x0009, 0x0005,
+  0x0028, 0x0011, 0x001f, 0x001d, 0x0011, 0x000d, 0x0004, 0x0002,
+  0x001b, 0x000c, 0x000b, 0x000f, 0x000a, 0x0007, 0x0004, 0x0001,
+  0x001b, 0x000c, 0x0008, 0x000c, 0x0006, 0x0003, 0x0001, 0x0000
+];
+
+static TABLE_12_LENGTHS: [u8; 64] = [
+   4,  3,  5,  7,  8,  9,  9,  9,  3,  3,  4,  5,  7,  7,  8,  8,
+   5,  4,  5,  6,  7,  8,  7,  8,  6,  5,  6,  6,  7,  8,  8,  8,
+   7,  6,  7,  7,  8,  8,  8,  9,  8,  7,  8,  8,  8,  9,  8,  9,
+   8,  7,  7,  8,  8,  9,  9, 10,  9,  8,  8,  9,  9,  9,  9, 10
+];
+
+static TABLE_13_CODES: [u32; 256] = [
+  0x0001, 0x0005, 0x000e, 0x0015, 0x0022, 0x0033, 0x002e, 0x0047,
+  0x002a, 0x0034, 0x0044, 0x0034, 0x0043, 0x002c, 0x002b, 0x0013,
+  0x0003, 0x0004, 0x000c, 0x0013, 0x001f, 0x001a, 0x002c, 0x0021,
+  0x001f, 0x0018, 0x0020, 0x0018, 0x001f, 0x0023, 0x0016, 0x000e,
+  0x000f, 0x000d, 0x0017, 0x0024, 0x003b, 0x0031, 0x004d, 0x0041,
+  0x001d, 0x0028, 0x001e, 0x0028, 0x001b, 0x0021, 0x002a, 0x0010,
+  0x0016, 0x0014, 0x0025, 0x003d, 0x0038, 0x004f, 0x0049, 0x0040,
+  0x002b, 0x004c, 0x0038, 0x0025, 0x001a, 0x001f, 0x0019, 0x000e,
+  0x0023, 0x0010, 0x003c, 0x0039, 0x0061, 0x004b, 0x0072, 0x005b,
+  0x0036, 0x0049, 0x0037, 0x0029, 0x0030, 0x0035, 0x0017, 0x0018,
+  0x003a, 0x001b, 0x0032, 0x0060, 0x004c, 0x0046, 0x005d, 0x0054,
+  0x004d, 0x003a, 0x004f, 0x001d, 0x004a, 0x0031, 0x0029, 0x0011,
+  0x002f, 0x002d, 0x004e, 0x004a, 0x0073, 0x005e, 0x005a, 0x004f,
+  0x0045, 0x0053, 0x0047, 0x0032, 0x003b, 0x0026, 0x0024, 0x000f,
+  0x0048, 0x0022, 0x0038, 0x005f, 0x005c, 0x0055, 0x005b, 0x005a,
+  0x0056, 0x0049, 0x004d, 0x0041, 0x0033, 0x002c, 0x002b, 0x002a,
+  0x002b, 0x0014, 0x001e, 0x002c, 0x0037, 0x004e, 0x0048, 0x0057,
+  0x004e, 0x003d, 0x002e, 0x0036, 0x0025, 0x001e, 0x0014, 0x0010,
+  0x0035, 0x0019, 0x0029, 0x0025, 0x002c, 0x003b, 0x0036, 0x0051,
+  0x0042, 0x004c, 0x0039, 0x0036, 0x0025, 0x0012, 0x0027, 0x000b,
+  0x0023, 0x0021, 0x001f, 0x0039, 0x002a, 0x0052, 0x0048, 0x0050,
+  0x002f, 0x003a, 0x0037, 0x0015, 0x0016, 0x001a, 0x0026, 0x0016,
+  0x0035, 0x0019, 0x0017, 0x0026, 0x0046, 0x003c, 0x0033, 0x0024,
+  0x0037, 0x001a, 0x0022, 0x0017, 0x001b, 0x000e, 0x0009, 0x0007,
+  0x0022, 0x0020, 0x001c, 0x0027, 0x0031, 0x004b, 0x001e, 0x0034,
+  0x0030, 0x0028, 0x0034, 0x001c, 0x0012, 0x0011, 0x0009, 0x0005,
+  0x002d, 0x0015, 0x0022, 0x0040, 0x0038, 0x0032, 0x0031, 0x002d,
+  0x001f, 0x0013, 0x000c, 0x000f, 0x000a, 0x0007, 0x0006, 0x0003,
+  0x0030, 0x0017, 0x0014, 0x0027, 0x0024, 0x0023, 0x0035, 0x0015,
+  0x0010, 0x0017, 0x000d, 0x000a, 0x0006, 0x0001, 0x0004, 0x0002,
+  0x0010, 0x000f, 0x0011, 0x001b, 0x0019, 0x0014, 0x001d, 0x000b,
+  0x0011, 0x000c, 0x0010, 0x0008, 0x0001, 0x0001, 0x0000, 0x0001
+];
+
+static TABLE_13_LENGTHS: [u8; 256] = [
+   1,  4,  6,  7,  8,  9,  9, 10,  9, 10, 11, 11, 12, 12, 13, 13,
+   3,  4,  6,  7,  8,  8,  9,  9,  9,  9, 10, 10, 11, 12, 12, 12,
+   6,  6,  7,  8,  9,  9, 10, 10,  9, 10, 10, 11, 11, 12, 13, 13,
+   7,  7,  8,  9,  9, 10, 10, 10, 10, 11, 11, 11, 11, 12, 13, 13,
+   8,  7,  9,  9, 10, 10, 11, 11, 10, 11, 11, 12, 12, 13, 13, 14,
+   9,  8,  9, 10, 10, 10, 11, 11, 11, 11, 12, 11, 13, 13, 14, 14,
+   9,  9, 10, 10, 11, 11, 11, 11, 11, 12, 12, 12, 13, 13, 14, 14,
+  10,  9, 10, 11, 11, 11, 12, 12, 12, 12, 13, 13, 13, 14, 16, 16,
+   9,  8,  9, 10, 10, 11, 11, 12, 12, 12, 12, 13, 13, 14, 15, 15,
+  10,  9, 10, 10, 11, 11, 11, 13, 12, 13, 13, 14, 14, 14, 16, 15,
+  10, 10, 10, 11, 11, 12, 12, 13, 12, 13, 14, 13, 14, 15, 16, 17,
+  11, 10, 10, 11, 12, 12, 12, 12, 13, 13, 13, 14, 15, 15, 15, 16,
+  11, 11, 11, 12, 12, 13, 12, 13, 14, 14, 15, 15, 15, 16, 16, 16,
+  12, 11, 12, 13, 13, 13, 14, 14, 14, 14, 14, 15, 16, 15, 16, 16,
+  13, 12, 12, 13, 13, 13, 15, 14, 14, 17, 15, 15, 15, 17, 16, 16,
+  12, 12, 13, 14, 14, 14, 15, 14, 15, 15, 16, 16, 19, 18, 19, 16
+];
+
+static TABLE_15_CODES: [u32; 256] = [
+  0x0007, 0x000c, 0x0012, 0x0035, 0x002f, 0x004c, 0x007c, 0x006c,
+  0x0059, 0x007b, 0x006c, 0x0077, 0x006b, 0x0051, 0x007a, 0x003f,
+  0x000d, 0x0005, 0x0010, 0x001b, 0x002e, 0x0024, 0x003d, 0x0033,
+  0x002a, 0x0046, 0x0034, 0x0053, 0x0041, 0x0029, 0x003b, 0x0024,
+  0x0013, 0x0011, 0x000f, 0x0018, 0x0029, 0x0022, 0x003b, 0x0030,
+  0x0028, 0x0040, 0x0032, 0x004e, 0x003e, 0x0050, 0x0038, 0x0021,
+  0x001d, 0x001c, 0x0019, 0x002b, 0x0027, 0x003f, 0x0037, 0x005d,
+  0x004c, 0x003b, 0x005d, 0x0048, 0x0036, 0x004b, 0x0032, 0x001d,
+  0x0034, 0x0016, 0x002a, 0x0028, 0x0043, 0x0039, 0x005f, 0x004f,
+  0x0048, 0x0039, 0x0059, 0x0045, 0x0031, 0x0042, 0x002e, 0x001b,
+  0x004d, 0x0025, 0x0023, 0x0042, 0x003a, 0x0034, 0x005b, 0x004a,
+  0x003e, 0x0030, 0x004f, 0x003f, 0x005a, 0x003e, 0x0028, 0x0026,
+  0x007d, 0x0020, 0x003c, 0x0038, 0x0032, 0x005c, 0x004e, 0x0041,
+  0x0037, 0x0057, 0x0047, 0x0033, 0x0049, 0x0033, 0x0046, 0x001e,
+  0x006d, 0x0035, 0x0031, 0x005e, 0x0058, 0x004b, 0x0042, 0x007a,
+  0x005b, 0x0049, 0x0038, 0x002a, 0x0040, 0x002c, 0x0015, 0x0019,
+  0x005a, 0x002b, 0x0029, 0x004d, 0x0049, 0x003f, 0x0038, 0x005c,
+  0x004d, 0x0042, 0x002f, 0x0043, 0x0030, 0x0035, 0x0024, 0x0014,
+  0x0047, 0x0022, 0x0043, 0x003c, 0x003a, 0x0031, 0x0058, 0x004c,
+  0x0043, 0x006a, 0x0047, 0x0036, 0x0026, 0x0027, 0x0017, 0x000f,
+  0x006d, 0x0035, 0x0033, 0x002f, 0x005a, 0x0052, 0x003a, 0x0039,
+  0x0030, 0x0048, 0x0039, 0x0029, 0x0017, 0x001b, 0x003e, 0x0009,
+  0x0056, 0x002a, 0x0028, 0x0025, 0x0046, 0x0040, 0x0034, 0x002b,
+  0x0046, 0x0037, 0x002a, 0x0019, 0x001d, 0x0012, 0x000b, 0x000b,
+  0x0076, 0x0044, 0x001e, 0x0037, 0x0032, 0x002e, 0x004a, 0x0041,
+  0x0031, 0x0027, 0x0018, 0x0010, 0x0016, 0x000d, 0x000e, 0x0007,
+  0x005b, 0x002c, 0x0027, 0x0026, 0x0022, 0x003f, 0x0034, 0x002d,
+  0x001f, 0x0034, 0x001c, 0x0013, 0x000e, 0x0008, 0x0009, 0x0003,
+  0x007b, 0x003c, 0x003a, 0x0035, 0x002f, 0x002b, 0x0020, 0x0016,
+  0x0025, 0x0018, 0x0011, 0x000c, 0x000f, 0x000a, 0x0002, 0x0001,
+  0x0047, 0x0025, 0x0022, 0x001e, 0x001c, 0x0014, 0x0011, 0x001a,
+  0x0015, 0x0010, 0x000a, 0x0006, 0x0008, 0x0006, 0x0002, 0x0000
+];
+
+static TABLE_15_LENGTHS: [u8; 256] = [
+   3,  4,  5,  7,  7,  8,  9,  9,  9, 10, 10, 11, 11, 11, 12, 13,
+   4,  3,  5,  6,  7,  7,  8,  8,  8,  9,  9, 10, 10, 10, 11, 11,
+   5,  5,  5,  6,  7,  7,  8,  8,  8,  9,  9, 10, 10, 11, 11, 11,
+   6,  6,  6,  7,  7,  8,  8,  9,  9,  9, 10, 10, 10, 11, 11, 11,
+   7,  6,  7,  7,  8,  8,  9,  9,  9,  9, 10, 10, 10, 11, 11, 11,
+   8,  7,  7,  8,  8,  8,  9,  9,  9,  9, 10, 10, 11, 11, 11, 12,
+   9,  7,  8,  8,  8,  9,  9,  9,  9, 10, 10, 10, 11, 11, 12, 12,
+   9,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10, 11, 11, 11, 12,
+   9,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 11, 11, 12, 12, 12,
+   9,  8,  9,  9,  9,  9, 10, 10, 10, 11, 11, 11, 11, 12, 12, 12,
+  10,  9,  9,  9, 10, 10, 10, 10, 10, 11, 11, 11, 11, 12, 13, 12,
+  10,  9,  9,  9, 10, 10, 10, 10, 11, 11, 11, 11, 12, 12, 12, 13,
+  11, 10,  9, 10, 10, 10, 11, 11, 11, 11, 11, 11, 12, 12, 13, 13,
+  11, 10, 10, 10, 10, 11, 11, 11, 11, 12, 12, 12, 12, 12, 13, 13,
+  12, 11, 11, 11, 11, 11, 11, 11, 12, 12, 12, 12, 13, 13, 12, 13,
+  12, 11, 11, 11, 11, 11, 11, 12, 12, 12, 12, 12, 13, 13, 13, 13
+];
+
+static TABLE_16_CODES: [u32; 256] = [
+  0x0001, 0x0005, 0x000e, 0x002c, 0x004a, 0x003f, 0x006e, 0x005d,
+  0x00ac, 0x0095, 0x008a, 0x00f2, 0x00e1, 0x00c3, 0x0178, 0x0011,
+  0x0003, 0x0004, 0x000c, 0x0014, 0x0023, 0x003e, 0x0035, 0x002f,
+  0x0053, 0x004b, 0x0044, 0x0077, 0x00c9, 0x006b, 0x00cf, 0x0009,
+  0x000f, 0x000d, 0x0017, 0x0026, 0x0043, 0x003a, 0x0067, 0x005a,
+  0x00a1, 0x0048, 0x007f, 0x0075, 0x006e, 0x00d1, 0x00ce, 0x0010,
+  0x002d, 0x0015, 0x0027, 0x0045, 0x0040, 0x0072, 0x0063, 0x0057,
+  0x009e, 0x008c, 0x00fc, 0x00d4, 0x00c7, 0x0183, 0x016d, 0x001a,
+  0x004b, 0x0024, 0x0044, 0x0041, 0x0073, 0x0065, 0x00b3, 0x00a4,
+  0x009b, 0x0108, 0x00f6, 0x00e2, 0x018b, 0x017e, 0x016a, 0x0009,
+  0x0042, 0x001e, 0x003b, 0x0038, 0x0066, 0x00b9, 0x00ad, 0x0109,
+  0x008e, 0x00fd, 0x00e8, 0x0190, 0x0184, 0x017a, 0x01bd, 0x0010,
+  0x006f, 0x0036, 0x0034, 0x0064, 0x00b8, 0x00b2, 0x00a0, 0x0085,
+  0x0101, 0x00f4, 0x00e4, 0x00d9, 0x0181, 0x016e, 0x02cb, 0x000a,
+  0x0062, 0x0030, 0x005b, 0x0058, 0x00a5, 0x009d, 0x0094, 0x0105,
+  0x00f8, 0x0197, 0x018d, 0x0174, 0x017c, 0x0379, 0x0374, 0x0008,
+  0x0055, 0x0054, 0x0051, 0x009f, 0x009c, 0x008f, 0x0104, 0x00f9,
+  0x01ab, 0x0191, 0x0188, 0x017f, 0x02d7, 0x02c9, 0x02c4, 0x0007,
+  0x009a, 0x004c, 0x0049, 0x008d, 0x0083, 0x0100, 0x00f5, 0x01aa,
+  0x0196, 0x018a, 0x0180, 0x02df, 0x0167, 0x02c6, 0x0160, 0x000b,
+  0x008b, 0x0081, 0x0043, 0x007d, 0x00f7, 0x00e9, 0x00e5, 0x00db,
+  0x0189, 0x02e7, 0x02e1, 0x02d0, 0x0375, 0x0372, 0x01b7, 0x0004,
+  0x00f3, 0x0078, 0x0076, 0x0073, 0x00e3, 0x00df, 0x018c, 0x02ea,
+  0x02e6, 0x02e0, 0x02d1, 0x02c8, 0x02c2, 0x00df, 0x01b4, 0x0006,
+  0x00ca, 0x00e0, 0x00de, 0x00da, 0x00d8, 0x0185, 0x0182, 0x017d,
+  0x016c, 0x0378, 0x01bb, 0x02c3, 0x01b8, 0x01b5, 0x06c0, 0x0004,
+  0x02eb, 0x00d3, 0x00d2, 0x00d0, 0x0172, 0x017b, 0x02de, 0x02d3,
+  0x02ca, 0x06c7, 0x0373, 0x036d, 0x036c, 0x0d83, 0x0361, 0x0002,
+  0x0179, 0x0171, 0x0066, 0x00bb, 0x02d6, 0x02d2, 0x0166, 0x02c7,
+  0x02c5, 0x0362, 0x06c6, 0x0367, 0x0d82, 0x0366, 0x01b2, 0x0000,
+  0x000c, 0x000a, 0x0007, 0x000b, 0x000a, 0x0011, 0x000b, 0x0009,
+  0x000d, 0x000c, 0x000a, 0x0007, 0x0005, 0x0003, 0x0001, 0x0003
+];
+
+static TABLE_16_LENGTHS: [u8; 256] = [
+   1,  4,  6,  8,  9,  9, 10, 10, 11, 11, 11, 12, 12, 12, 13,  9,
+   3,  4,  6,  7,  8,  9,  9,  9, 10, 10, 10, 11, 12, 11, 12,  8,
+   6,  6,  7,  8,  9,  9, 10, 10, 11, 10, 11, 11, 11, 12, 12,  9,
+   8,  7,  8,  9,  9, 10, 10, 10, 11, 11, 12, 12, 12, 13, 13, 10,
+   9,  8,  9,  9, 10, 10, 11, 11, 11, 12, 12, 12, 13, 13, 13,  9,
+   9,  8,  9,  9, 10, 11, 11, 12, 11, 12, 12, 13, 13, 13, 14, 10,
+  10,  9,  9, 10, 11, 11, 11, 11, 12, 12, 12, 12, 13, 13, 14, 10,
+  10,  9, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 13, 15, 15, 10,
+  10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 13, 14, 14, 14, 10,
+  11, 10, 10, 11, 11, 12, 12, 13, 13, 13, 13, 14, 13, 14, 13, 11,
+  11, 11, 10, 11, 12, 12, 12, 12, 13, 14, 14, 14, 15, 15, 14, 10,
+  12, 11, 11, 11, 12, 12, 13, 14, 14, 14, 14, 14, 14, 13, 14, 11,
+  12, 12, 12, 12, 12, 13, 13, 13, 13, 15, 14, 14, 14, 14, 16, 11,
+  14, 12, 12, 12, 13, 13, 14, 14, 14, 16, 15, 15, 15, 17, 15, 11,
+  13, 13, 11, 12, 14, 14, 13, 14, 14, 15, 16, 15, 17, 15, 14, 11,
+   9,  8,  8,  9,  9, 10, 10, 10, 11, 11, 11, 11, 11, 11, 11,  8
+];
+
+static TABLE_24_CODES: [u32; 256] = [
+  0x000f, 0x000d, 0x002e, 0x0050, 0x0092, 0x0106, 0x00f8, 0x01b2,
+  0x01aa, 0x029d, 0x028d, 0x0289, 0x026d, 0x0205, 0x0408, 0x0058,
+  0x000e, 0x000c, 0x0015, 0x0026, 0x0047, 0x0082, 0x007a, 0x00d8,
+  0x00d1, 0x00c6, 0x0147, 0x0159, 0x013f, 0x0129, 0x0117, 0x002a,
+  0x002f, 0x0016, 0x0029, 0x004a, 0x0044, 0x0080, 0x0078, 0x00dd,
+  0x00cf, 0x00c2, 0x00b6, 0x0154, 0x013b, 0x0127, 0x021d, 0x0012,
+  0x0051, 0x0027, 0x004b, 0x0046, 0x0086, 0x007d, 0x0074, 0x00dc,
+  0x00cc, 0x00be, 0x00b2, 0x0145, 0x0137, 0x0125, 0x010f, 0x0010,
+  0x0093, 0x0048, 0x0045, 0x0087, 0x007f, 0x0076, 0x0070, 0x00d2,
+  0x00c8, 0x00bc, 0x0160, 0x0143, 0x0132, 0x011d, 0x021c, 0x000e,
+  0x0107, 0x0042, 0x0081, 0x007e, 0x0077, 0x0072, 0x00d6, 0x00ca,
+  0x00c0, 0x00b4, 0x0155, 0x013d, 0x012d, 0x0119, 0x0106, 0x000c,
+  0x00f9, 0x007b, 0x0079, 0x0075, 0x0071, 0x00d7, 0x00ce, 0x00c3,
+  0x00b9, 0x015b, 0x014a, 0x0134, 0x0123, 0x0110, 0x0208, 0x000a,
+  0x01b3, 0x0073, 0x006f, 0x006d, 0x00d3, 0x00cb, 0x00c4, 0x00bb,
+  0x0161, 0x014c, 0x0139, 0x012a, 0x011b, 0x0213, 0x017d, 0x0011,
+  0x01ab, 0x00d4, 0x00d0, 0x00cd, 0x00c9, 0x00c1, 0x00ba, 0x00b1,
+  0x00a9, 0x0140, 0x012f, 0x011e, 0x010c, 0x0202, 0x0179, 0x0010,
+  0x014f, 0x00c7, 0x00c5, 0x00bf, 0x00bd, 0x00b5, 0x00ae, 0x014d,
+  0x0141, 0x0131, 0x0121, 0x0113, 0x0209, 0x017b, 0x0173, 0x000b,
+  0x029c, 0x00b8, 0x00b7, 0x00b3, 0x00af, 0x0158, 0x014b, 0x013a,
+  0x0130, 0x0122, 0x0115, 0x0212, 0x017f, 0x0175, 0x016e, 0x000a,
+  0x028c, 0x015a, 0x00ab, 0x00a8, 0x00a4, 0x013e, 0x0135, 0x012b,
+  0x011f, 0x0114, 0x0107, 0x0201, 0x0177, 0x0170, 0x016a, 0x0006,
+  0x0288, 0x0142, 0x013c, 0x0138, 0x0133, 0x012e, 0x0124, 0x011c,
+  0x010d, 0x0105, 0x0200, 0x0178, 0x0172, 0x016c, 0x0167, 0x0004,
+  0x026c, 0x012c, 0x0128, 0x0126, 0x0120, 0x011a, 0x0111, 0x010a,
+  0x0203, 0x017c, 0x0176, 0x0171, 0x016d, 0x0169, 0x0165, 0x0002,
+  0x0409, 0x0118, 0x0116, 0x0112, 0x010b, 0x0108, 0x0103, 0x017e,
+  0x017a, 0x0174, 0x016f, 0x016b, 0x0168, 0x0166, 0x0164, 0x0000,
+  0x002b, 0x0014, 0x0013, 0x0011, 0x000f, 0x000d, 0x000b, 0x0009,
+  0x0007, 0x0006, 0x0004, 0x0007, 0x0005, 0x0003, 0x0001, 0x0003
+];
+
+static TABLE_24_LENGTHS: [u8; 256] = [
+   4,  4,  6,  7,  8,  9,  9, 10, 10, 11, 11, 11, 11, 11, 12,  9,
+   4,  4,  5,  6,  7,  8,  8,  9,  9,  9, 10, 10, 10, 10, 10,  8,
+   6,  5,  6,  7,  7,  8,  8,  9,  9,  9,  9, 10, 10, 10, 11,  7,
+   7,  6,  7,  7,  8,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10,  7,
+   8,  7,  7,  8,  8,  8,  8,  9,  9,  9, 10, 10, 10, 10, 11,  7,
+   9,  7,  8,  8,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10,  7,
+   9,  8,  8,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10, 11,  7,
+  10,  8,  8,  8,  9,  9,  9,  9, 10, 10, 10, 10, 10, 11, 11,  8,
+  10,  9,  9,  9,  9,  9,  9,  9,  9, 10, 10, 10, 10, 11, 11,  8,
+  10,  9,  9,  9,  9,  9,  9, 10, 10, 10, 10, 10, 11, 11, 11,  8,
+  11,  9,  9,  9,  9, 10, 10, 10, 10, 10, 10, 11, 11, 11, 11,  8,
+  11, 10,  9,  9,  9, 10, 10, 10, 10, 10, 10, 11, 11, 11, 11,  8,
+  11, 10, 10, 10, 10, 10, 10, 10, 10, 10, 11, 11, 11, 11, 11,  8,
+  11, 10, 10, 10, 10, 10, 10, 10, 11, 11, 11, 11, 11, 11, 11,  8,
+  12, 10, 10, 10, 10, 10, 10, 11, 11, 11, 11, 11, 11, 11, 11,  8,
+   8,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  8,  8,  8,  8,  4
+];
+
+static TABLE_A_CODES: [u32; 16] = [
+  0x0001, 0x0005, 0x0004, 0x0005, 0x0006, 0x0005, 0x0004, 0x0004,
+  0x0007, 0x0003, 0x0006, 0x0000, 0x0007, 0x0002, 0x0003, 0x0001
+];
+
+static TABLE_A_LENGTHS: [u8; 16] = [
+   1,  4,  4,  5,  4,  6,  5,  6,  4,  5,  5,  6,  5,  6,  6,  6
+];
+
+/// Quadruples in count1 table B are coded as their inverted bits.
+static TABLE_B_CODES: [u32; 16] = [
+  0x000f, 0x000e, 0x000d, 0x000c, 0x000b, 0x000a, 0x0009, 0x0008,
+  0x0007, 0x0006, 0x0005, 0x0004, 0x0003, 0x0002, 0x0001, 0x0000
+];
+
+static TABLE_B_LENGTHS: [u8; 16] = [
+   4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4,  4
+];
+
+/// A code table from table 3-B.7 of ISO 11172-3. The symbol of the code at
+/// index `i` is the pair (i / size, i % size).
+struct CodeTable {
+  codes: &'static [u32],
+  lengths: &'static [u8],
+  size: usize,
+  linbits: u32
+}
+
+static EMPTY_CODES: [u32; 0] = [];
+static EMPTY_LENGTHS: [u8; 0] = [];
+
+/// The big value tables by table_select. Tables 0, 4 and 14 have no codes,
+/// and tables 16 to 31 share two code tables with different linbits.
+static BIG_VALUE_TABLES: [CodeTable; 32] = [
+  CodeTable { codes: &EMPTY_CODES, lengths: &EMPTY_LENGTHS, size: 0, linbits: 0 },
+  CodeTable { codes: &TABLE_1_CODES, lengths: &TABLE_1_LENGTHS, size: 2, linbits: 0 },
+  CodeTable { codes: &TABLE_2_CODES, lengths: &TABLE_2_LENGTHS, size: 3, linbits: 0 },
+  CodeTable { codes: &TABLE_3_CODES, lengths: &TABLE_3_LENGTHS, size: 3, linbits: 0 },
+  CodeTable { codes: &EMPTY_CODES, lengths: &EMPTY_LENGTHS, size: 0, linbits: 0 },
+  CodeTable { codes: &TABLE_5_CODES, lengths: &TABLE_5_LENGTHS, size: 4, linbits: 0 },
+  CodeTable { codes: &TABLE_6_CODES, lengths: &TABLE_6_LENGTHS, size: 4, linbits: 0 },
+  CodeTable { codes: &TABLE_7_CODES, lengths: &TABLE_7_LENGTHS, size: 6, linbits: 0 },
+  CodeTable { codes: &TABLE_8_CODES, lengths: &TABLE_8_LENGTHS, size: 6, linbits: 0 },
+  CodeTable { codes: &TABLE_9_CODES, lengths: &TABLE_9_LENGTHS, size: 6, linbits: 0 },
+  CodeTable { codes: &TABLE_10_CODES, lengths: &TABLE_10_LENGTHS, size: 8, linbits: 0 },
+  CodeTable { codes: &TABLE_11_CODES, lengths: &TABLE_11_LENGTHS, size: 8, linbits: 0 },
+  CodeTable { codes: &TABLE_12_CODES, lengths: &TABLE_12_LENGTHS, size: 8, linbits: 0 },
+  CodeTable { codes: &TABLE_13_CODES, lengths: &TABLE_13_LENGTHS, size: 16, linbits: 0 },
+  CodeTable { codes: &EMPTY_CODES, lengths: &EMPTY_LENGTHS, size: 0, linbits: 0 },
+  CodeTable { codes: &TABLE_15_CODES, lengths: &TABLE_15_LENGTHS, size: 16, linbits: 0 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 1 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 2 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 3 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 4 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 6 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 8 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 10 },
+  CodeTable { codes: &TABLE_16_CODES, lengths: &TABLE_16_LENGTHS, size: 16, linbits: 13 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 4 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 5 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 6 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 7 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 8 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 9 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 11 },
+  CodeTable { codes: &TABLE_24_CODES, lengths: &TABLE_24_LENGTHS, size: 16, linbits: 13 }
+];
+
+/// A binary decoding tree built from a code table. Children are indices of
+/// other nodes, or leaves holding -(symbol + 1). A child of 0 means that no
+/// code goes that way, since the root is never a child.
+struct HuffmanTree {
+  nodes: Vec<[i32; 2]>
+}
+
+impl HuffmanTree {
+  fn new(codes: &[u32], lengths: &[u8]) -> HuffmanTree {
+    let mut nodes = vec![[0i32; 2]];
+
+    for symbol in 0..codes.len() {
+      let mut node = 0;
+
+      for i in (0..lengths[symbol] as u32).rev() {
+        let bit = ((codes[symbol] >> i) & 1) as usize;
+
+        if i == 0 {
+          nodes[node][bit] = -(symbol as i32 + 1);
+        } else {
+          if nodes[node][bit] <= 0 {
+            nodes.push([0i32; 2]);
+            nodes[node][bit] = (nodes.len() - 1) as i32;
+          }
+
+          node = nodes[node][bit] as usize;
+        }
+      }
+    }
+
+    HuffmanTree { nodes }
+  }
+
+  fn decode(&self, bit_reader: &mut bitreader::BitReader) -> error::Result<usize> {
+    let mut node = 0;
+
+    loop {
+      let bit = match bit_reader.read_bits(1) { Ok(b) => b as usize, Err(e) => return Err(e) };
+      let next = self.nodes[node][bit];
+
+      if next < 0 {
+        return Ok((-next - 1) as usize);
+      } else if next == 0 {
+        return Err(error::Error::InvalidData("invalid Huffman code", None));
+      }
+
+      node = next as usize;
+    }
+  }
+}
+
+/// Decodes the Huffman coded spectral values of Layer III (2.4.2.7).
+pub struct HuffmanDecoder {
+  big_values: Vec<HuffmanTree>,
+  count1: [HuffmanTree; 2]
+}
+
+impl HuffmanDecoder {
+  pub fn new() -> HuffmanDecoder {
+    HuffmanDecoder {
+      big_values: BIG_VALUE_TABLES.iter().map(|t| HuffmanTree::new(t.codes, t.lengths)).collect(),
+      count1: [HuffmanTree::new(&TABLE_A_CODES, &TABLE_A_LENGTHS), HuffmanTree::new(&TABLE_B_CODES, &TABLE_B_LENGTHS)]
+    }
+  }
+
+  /// Decodes a pair of big values with `table_select`, including their
+  /// linbits and signs.
+  pub fn decode_pair(&self, bit_reader: &mut bitreader::BitReader, table_select: u32) -> error::Result<(i32, i32)> {
+    let table = &BIG_VALUE_TABLES[table_select as usize];
+
+    if table_select == 0 {
+      return Ok((0, 0));
+    } else if table.size == 0 {
+      return Err(error::Error::ReservedField("table_select", None));
+    }
+
+    let symbol = self.big_values[table_select as usize].decode(bit_reader)?;
+
+    let x = read_value(bit_reader, (symbol / table.size) as i32, table.linbits)?;
+    let y = read_value(bit_reader, (symbol % table.size) as i32, table.linbits)?;
+
+    Ok((x, y))
+  }
+
+  /// Decodes a quadruple of values in the range [-1, 1] with count1 table A
+  /// or B.
+  pub fn decode_quad(&self, bit_reader: &mut bitreader::BitReader, count1table_select: bool) -> error::Result<[i32; 4]> {
+    let symbol = self.count1[count1table_select as usize].decode(bit_reader)?;
+    let mut values = [0i32; 4];
+
+    for (i, value) in values.iter_mut().enumerate() {
+      *value = read_value(bit_reader, ((symbol >> (3 - i)) & 1) as i32, 0)?;
+    }
+
+    Ok(values)
+  }
+}
+
+/// Reads the linbits extending a value of 15, and the sign bit of non-zero
+/// values.
+fn read_value(bit_reader: &mut bitreader::BitReader, value: i32, linbits: u32) -> error::Result<i32> {
+  let mut value = value;
+
+  if linbits > 0 && value == 15 {
+    value += match bit_reader.read_bits(linbits) { Ok(v) => v as i32, Err(e) => return Err(e) };
+  }
+
+  if value != 0 {
+    match bit_reader.read_bits(1) {
+      Ok(1) => value = -value,
+      Ok(_) => {},
+      Err(e) => return Err(e)
+    };
+  }
+
+  Ok(value)
+}
+
+#[cfg(test)]
+use std::io;
+#[cfg(test)]
+use std::io::Cursor;
+
+#[test]
+fn test_decode_pair() {
+  // Table 1: 01 is (1, 0), followed by a negative sign. 1 is (0, 0).
+  let buf = Cursor::new(vec![0x70]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+  let decoder = HuffmanDecoder::new();
+
+  assert_eq!(decoder.decode_pair(&mut r, 1).unwrap(), (-1, 0));
+  assert_eq!(decoder.decode_pair(&mut r, 1).unwrap(), (0, 0));
+  assert_eq!(r.position(), 4);
+}
+
+#[test]
+fn test_decode_pair_with_linbits() {
+  // Table 16 (1 linbit): 00000011 is (15, 15). x gets linbits 1 and a
+  // positive sign, y gets linbits 0 and a negative sign.
+  let buf = Cursor::new(vec![0x03, 0x90]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+  let decoder = HuffmanDecoder::new();
+
+  assert_eq!(decoder.decode_pair(&mut r, 16).unwrap(), (16, -15));
+  assert_eq!(r.position(), 12);
+}
+
+#[test]
+fn test_decode_quad() {
+  // Table A: 0101 is 0001, table B: 1010 is 0101. Each non-zero value is
+  // followed by its sign.
+  let buf = Cursor::new(vec![0x5D, 0x20]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+  let decoder = HuffmanDecoder::new();
+
+  assert_eq!(decoder.decode_quad(&mut r, false).unwrap(), [0, 0, 0, -1]);
+  assert_eq!(decoder.decode_quad(&mut r, true).unwrap(), [0, 1, 0, -1]);
+}
+
+#[test]
+fn test_invalid_table() {
+  let buf = Cursor::new(vec![0x00]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+  let decoder = HuffmanDecoder::new();
+
+  assert!(decoder.decode_pair(&mut r, 4).is_err());
+  assert!(decoder.decode_pair(&mut r, 14).is_err());
+}
+
+#[test]
+fn test_codes_are_complete() {
+  for table in BIG_VALUE_TABLES.iter().filter(|t| t.size > 0) {
+    let sum: f64 = table.lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+
+    assert_eq!(table.codes.len(), table.size * table.size);
+    assert!((sum - 1.0).abs() < 1e-12);
+  }
+}
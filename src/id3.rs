@@ -0,0 +1,1053 @@
+use std::io;
+use std::io::SeekFrom;
+
+use error;
+#[cfg(test)]
+use decoder;
+
+/// The size of the header of an ID3v2 tag, and of the footer of an ID3v2.4
+/// tag.
+pub const HEADER_SIZE: usize = 10;
+
+/// The padding given to a tag that no longer fits in front of the frames,
+/// so that it can grow a little before the file has to be rewritten again.
+const PADDING: usize = 1024;
+
+/// The number of bytes moved at a time when the file is rewritten.
+const COPY_SIZE: usize = 65536;
+
+/// The flags of the tag header.
+const UNSYNCHRONISATION: u8 = 0x80;
+const EXTENDED_HEADER: u8 = 0x40;
+const FOOTER: u8 = 0x10;
+
+/// A frame of an ID3v2 tag. The frame IDs of ID3v2.2 tags are given as
+/// their ID3v2.3 counterparts where there are any, so that "TT2" reads as
+/// "TIT2".
+#[derive(Debug,Clone,PartialEq)]
+pub enum Frame {
+  /// A text information frame such as TIT2 for the title, with its values.
+  /// Only ID3v2.4 allows more than one value.
+  Text { id: String, values: Vec<String> },
+  /// A TXXX frame.
+  UserText { description: String, value: String },
+  /// A COMM frame, with the ISO 639-2 code of the language of the comment.
+  Comment { language: String, description: String, text: String },
+  /// A URL link frame such as WOAR for the artist's web page.
+  Url { id: String, url: String },
+  /// A WXXX frame.
+  UserUrl { description: String, url: String },
+  /// An APIC frame.
+  Picture(Picture),
+  /// A CHAP frame.
+  Chapter(Chapter),
+  /// A CTOC frame.
+  TableOfContents(TableOfContents),
+  /// Any other frame, or a frame that cannot be read because it is
+  /// compressed, encrypted or malformed, with its data. A compressed or
+  /// encrypted frame keeps its data as it was along with the version of its
+  /// tag and its format flags, which it is only written back with.
+  Other { id: String, data: Vec<u8>, format: Option<(u8, u8)> }
+}
+
+impl Frame {
+  pub fn id(&self) -> &str {
+    match *self {
+      Frame::Text { ref id, .. } | Frame::Url { ref id, .. } | Frame::Other { ref id, .. } => id,
+      Frame::UserText { .. } => "TXXX",
+      Frame::Comment { .. } => "COMM",
+      Frame::UserUrl { .. } => "WXXX",
+      Frame::Picture(_) => "APIC",
+      Frame::Chapter(_) => "CHAP",
+      Frame::TableOfContents(_) => "CTOC"
+    }
+  }
+}
+
+/// An attached picture, such as the front cover, with the picture type
+/// ID3v2 gives it: 3 for the front cover, 4 for the back cover and so on.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Picture {
+  pub mime_type: String,
+  pub picture_type: u8,
+  pub description: String,
+  pub data: Vec<u8>
+}
+
+/// A chapter, with its start and end times in milliseconds and the byte
+/// offsets of the frames where it starts and ends, when they are given. Its
+/// frames, such as TIT2, describe the chapter.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Chapter {
+  pub element_id: String,
+  pub start_time: u32,
+  pub end_time: u32,
+  pub start_offset: Option<u32>,
+  pub end_offset: Option<u32>,
+  pub frames: Vec<Frame>
+}
+
+impl Chapter {
+  /// The index of the sample the chapter starts at in the output of the
+  /// decoder at `samplerate`, which `Decoder::seek` takes.
+  pub fn start_sample(&self, samplerate: u32) -> u64 {
+    time_to_sample(self.start_time, samplerate)
+  }
+
+  /// The index of the sample following the chapter in the output of the
+  /// decoder at `samplerate`.
+  pub fn end_sample(&self, samplerate: u32) -> u64 {
+    time_to_sample(self.end_time, samplerate)
+  }
+}
+
+/// A table of contents, listing the element IDs of the chapters or of other
+/// tables of contents that it holds. Only one of them is at the top level.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TableOfContents {
+  pub element_id: String,
+  pub top_level: bool,
+  pub ordered: bool,
+  pub children: Vec<String>,
+  pub frames: Vec<Frame>
+}
+
+/// The flags of a CTOC frame.
+const TOP_LEVEL: u8 = 0x02;
+const ORDERED: u8 = 0x01;
+
+/// The byte offset of a CHAP frame that gives none.
+const NO_OFFSET: u32 = 0xFFFFFFFF;
+
+/// An ID3v2 tag.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Tag {
+  /// The major version and revision, such as 4 and 0 for ID3v2.4.0.
+  pub version: u8,
+  pub revision: u8,
+  pub frames: Vec<Frame>,
+  /// The size of the tag in bytes, header and footer included, and the
+  /// padding following its frames.
+  pub size: usize,
+  pub padding: usize
+}
+
+impl Tag {
+  /// An empty tag of ID3v2.3 or ID3v2.4.
+  pub fn new(version: u8) -> Tag {
+    Tag { version, revision: 0, frames: Vec::new(), size: 0, padding: 0 }
+  }
+
+  /// The first value of the text frame `id`, such as "TIT2" for the title.
+  pub fn text(&self, id: &str) -> Option<&str> {
+    for frame in self.frames.iter() {
+      match *frame {
+        Frame::Text { id: ref i, ref values } if i == id => return values.first().map(|v| v.as_str()),
+        _ => {}
+      };
+    }
+
+    None
+  }
+
+  /// Sets the values of the text frame `id`, replacing the frame when there
+  /// is one.
+  pub fn set_text(&mut self, id: &str, values: Vec<String>) {
+    let frame = Frame::Text { id: id.to_string(), values };
+
+    match self.frames.iter().position(|f| f.id() == id) {
+      Some(i) => self.frames[i] = frame,
+      None => self.frames.push(frame)
+    };
+  }
+
+  /// The attached pictures.
+  pub fn pictures(&self) -> Vec<&Picture> {
+    self.frames.iter().filter_map(|f| match *f { Frame::Picture(ref p) => Some(p), _ => None }).collect()
+  }
+
+  /// The chapters in the order of their start times.
+  pub fn chapters(&self) -> Vec<&Chapter> {
+    let mut chapters: Vec<&Chapter> = self.frames.iter().filter_map(|f| match *f { Frame::Chapter(ref c) => Some(c), _ => None }).collect();
+
+    chapters.sort_by_key(|c| c.start_time);
+
+    chapters
+  }
+
+  /// The chapter with the element ID `element_id`.
+  pub fn chapter(&self, element_id: &str) -> Option<&Chapter> {
+    self.chapters().into_iter().find(|c| c.element_id == element_id)
+  }
+
+  /// The table of contents at the top level.
+  pub fn table_of_contents(&self) -> Option<&TableOfContents> {
+    for frame in self.frames.iter() {
+      match *frame {
+        Frame::TableOfContents(ref toc) if toc.top_level => return Some(toc),
+        _ => {}
+      };
+    }
+
+    None
+  }
+
+  /// Removes the frames with the ID `id`.
+  pub fn remove(&mut self, id: &str) {
+    self.frames.retain(|f| f.id() != id);
+  }
+
+  /// Writes the tag with its padding. An ID3v2.4 tag is written as such,
+  /// and any other as an ID3v2.3 tag. Neither unsynchronisation nor an
+  /// extended header or footer is written, and frames whose ID3v2.2 IDs
+  /// have no ID3v2.3 counterpart are left out.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    encode(self, self.padding)
+  }
+}
+
+/// Writes `tag` in place of the ID3v2 tag at the start of `file`, or in
+/// front of everything in it when it has none. The new tag takes the place
+/// of the old one with the rest padded when it fits, and otherwise the rest
+/// of the file is moved back to make room for it, leaving the frames as
+/// they are either way.
+pub fn write_tag<F: io::Read + io::Write + io::Seek>(file: &mut F, tag: &Tag) -> error::Result<()> {
+  let mut header = [0u8; HEADER_SIZE];
+
+  let read = match file.seek(SeekFrom::Start(0)) {
+    Ok(_) => file.read_exact(&mut header),
+    Err(e) => Err(e)
+  };
+
+  let old_size = match read {
+    Ok(_) => tag_size(&header).unwrap_or(0),
+    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+    Err(e) => return Err(error::Error::Io(e))
+  };
+
+  let size = encode(tag, 0).len();
+
+  let data = if old_size > 0 && size <= old_size {
+    encode(tag, old_size - size)
+  } else {
+    match move_back(file, old_size as u64, (size + PADDING - old_size) as u64) {
+      Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+    };
+
+    encode(tag, PADDING)
+  };
+
+  match file.seek(SeekFrom::Start(0)) {
+    Ok(_) => file.write_all(&data).map_err(error::Error::Io),
+    Err(e) => Err(error::Error::Io(e))
+  }
+}
+
+/// Moves what follows `start` in `file` back by `distance` bytes, starting
+/// from the end so that nothing is overwritten before it is moved.
+fn move_back<F: io::Read + io::Write + io::Seek>(file: &mut F, start: u64, distance: u64) -> io::Result<()> {
+  let mut end = file.seek(SeekFrom::End(0))?;
+
+  let mut chunk = vec![0u8; COPY_SIZE];
+
+  while end > start {
+    let from = if end - start > COPY_SIZE as u64 { end - COPY_SIZE as u64 } else { start };
+    let chunk = &mut chunk[..(end - from) as usize];
+
+    match file.seek(SeekFrom::Start(from)).and_then(|_| file.read_exact(chunk)) {
+      Ok(_) => {}, Err(e) => return Err(e)
+    };
+
+    match file.seek(SeekFrom::Start(from + distance)).and_then(|_| file.write_all(chunk)) {
+      Ok(_) => {}, Err(e) => return Err(e)
+    };
+
+    end = from;
+  }
+
+  Ok(())
+}
+
+/// Writes `tag` followed by `padding` zero bytes.
+fn encode(tag: &Tag, padding: usize) -> Vec<u8> {
+  let version = if tag.version == 4 { 4 } else { 3 };
+  let mut body = encode_frames(&tag.frames, version);
+
+  body.extend(vec![0u8; padding]);
+
+  let mut data = vec![b'I', b'D', b'3', version, 0, 0];
+
+  data.extend(&to_synchsafe(body.len() as u32));
+  data.extend(body);
+
+  data
+}
+
+/// Writes `frames` with their headers, as the body of a tag or the
+/// frames of a chapter or table of contents.
+fn encode_frames(frames: &[Frame], version: u8) -> Vec<u8> {
+  let mut body = Vec::new();
+
+  for frame in frames.iter() {
+    if frame.id().len() != 4 {
+      continue;
+    }
+
+    // The format flags of a frame kept as it was mean something else in
+    // another version, so such a frame is left out there.
+    let format = match *frame {
+      Frame::Other { format: Some((v, flags)), .. } => if v == version { flags } else { continue },
+      _ => 0
+    };
+
+    let data = encode_frame(frame, version);
+
+    body.extend(frame.id().as_bytes());
+    body.extend(&if version == 4 { to_synchsafe(data.len() as u32) } else { to_u32(data.len() as u32) });
+    body.extend(&[0, format]);
+    body.extend(data);
+  }
+
+  body
+}
+
+fn encode_frame(frame: &Frame, version: u8) -> Vec<u8> {
+  // Before ID3v2.4 a text frame holds a single value, so values are joined
+  // with slashes.
+  let text = match *frame {
+    Frame::Text { ref values, .. } => if version == 3 { vec![values.join("/")] } else { values.clone() },
+    Frame::UserText { ref description, ref value } => vec![description.clone(), value.clone()],
+    Frame::Comment { ref description, ref text, .. } => vec![description.clone(), text.clone()],
+    Frame::UserUrl { ref description, .. } => vec![description.clone()],
+    Frame::Picture(ref picture) => vec![picture.description.clone()],
+    Frame::Chapter(ref chapter) => {
+      let mut data: Vec<u8> = chapter.element_id.chars().map(|c| c as u8).collect();
+
+      data.push(0);
+
+      for &value in [chapter.start_time, chapter.end_time, chapter.start_offset.unwrap_or(NO_OFFSET), chapter.end_offset.unwrap_or(NO_OFFSET)].iter() {
+        data.extend(&to_u32(value));
+      }
+
+      data.extend(encode_frames(&chapter.frames, version));
+
+      return data;
+    },
+    Frame::TableOfContents(ref toc) => {
+      let mut data: Vec<u8> = toc.element_id.chars().map(|c| c as u8).collect();
+
+      data.push(0);
+      data.push(if toc.top_level { TOP_LEVEL } else { 0 } | if toc.ordered { ORDERED } else { 0 });
+      data.push(toc.children.len() as u8);
+
+      for child in toc.children.iter() {
+        data.extend(child.chars().map(|c| c as u8));
+        data.push(0);
+      }
+
+      data.extend(encode_frames(&toc.frames, version));
+
+      return data;
+    },
+    Frame::Url { ref url, .. } => return url.chars().map(|c| c as u8).collect(),
+    Frame::Other { ref data, .. } => return data.clone()
+  };
+
+  // Text that ISO-8859-1 cannot hold is written in UTF-8, or in UTF-16 where
+  // ID3v2.3 has no UTF-8.
+  let encoding = if text.iter().all(|t| t.chars().all(|c| (c as u32) < 0x100)) { 0 } else if version == 4 { 3 } else { 1 };
+  let terminator: &[u8] = if encoding == 1 { &[0, 0] } else { &[0] };
+  let mut data = vec![encoding];
+
+  match *frame {
+    Frame::Comment { ref language, .. } => data.extend(language.bytes().chain("   ".bytes()).take(3)),
+    Frame::Picture(ref picture) => {
+      data.extend(picture.mime_type.chars().map(|c| c as u8));
+      data.push(0);
+      data.push(picture.picture_type);
+    },
+    _ => {}
+  };
+
+  for (i, t) in text.iter().enumerate() {
+    if i > 0 {
+      data.extend(terminator);
+    }
+
+    match encoding {
+      0 => data.extend(t.chars().map(|c| c as u8)),
+      1 => {
+        data.extend(&[0xFF, 0xFE]);
+
+        for unit in t.encode_utf16() {
+          data.extend(&[unit as u8, (unit >> 8) as u8]);
+        }
+      },
+      _ => data.extend(t.bytes())
+    };
+  }
+
+  match *frame {
+    Frame::UserUrl { ref url, .. } => {
+      data.extend(terminator);
+      data.extend(url.chars().map(|c| c as u8));
+    },
+    Frame::Picture(ref picture) => {
+      data.extend(terminator);
+      data.extend(&picture.data);
+    },
+    _ => {}
+  };
+
+  data
+}
+
+/// The size in bytes of the ID3v2 tag starting `data`, header and footer
+/// included, or None when `data` does not start with a tag header.
+pub fn tag_size(data: &[u8]) -> Option<usize> {
+  if data.len() < HEADER_SIZE || &data[..3] != b"ID3" {
+    return None;
+  }
+
+  let version = data[3];
+
+  if !(2..=4).contains(&version) || data[4] == 0xFF {
+    return None;
+  }
+
+  let footer = if version == 4 && data[5] & FOOTER != 0 { HEADER_SIZE } else { 0 };
+
+  synchsafe(&data[6..10]).map(|size| HEADER_SIZE + size as usize + footer)
+}
+
+/// Parses the ID3v2 tag starting `data`, which has to hold all of it. Frames
+/// are read up to the padding, or up to the first frame that runs past the
+/// end of the tag.
+pub fn parse(data: &[u8]) -> error::Result<Tag> {
+  let size = match tag_size(data) {
+    Some(s) if s <= data.len() => s,
+    _ => return Err(error::Error::InvalidData("invalid ID3v2 tag", None))
+  };
+
+  let version = data[3];
+  let flags = data[5];
+  let footer = if version == 4 && flags & FOOTER != 0 { HEADER_SIZE } else { 0 };
+
+  // Before ID3v2.4 the whole tag is unsynchronised at once, and from then
+  // on each frame on its own.
+  let body = if version < 4 && flags & UNSYNCHRONISATION != 0 {
+    resynchronise(&data[HEADER_SIZE..size - footer])
+  } else {
+    data[HEADER_SIZE..size - footer].to_vec()
+  };
+
+  let mut tag = Tag { version, revision: data[4], frames: Vec::new(), size, padding: 0 };
+
+  // The same flag tells that an ID3v2.2 tag is compressed, in a way that
+  // was never defined.
+  if version == 2 && flags & EXTENDED_HEADER != 0 {
+    return Ok(tag);
+  }
+
+  let mut position = 0;
+
+  if version > 2 && flags & EXTENDED_HEADER != 0 {
+    let extended_size = if body.len() < 4 {
+      None
+    } else if version == 4 {
+      synchsafe(&body[..4]).map(|s| s as usize)
+    } else {
+      Some(4 + read_u32(&body[..4]) as usize)
+    };
+
+    position = match extended_size {
+      Some(s) if s <= body.len() => s,
+      _ => return Err(error::Error::InvalidData("invalid ID3v2 extended header", None))
+    };
+  }
+
+  let (frames, size) = parse_frames(version, flags, &body[position..]);
+
+  tag.frames = frames;
+  tag.padding = body.len() - position - size;
+
+  Ok(tag)
+}
+
+/// Parses the frames that start `body`, up to the padding or up to the
+/// first frame that runs past the end of `body`, and returns them along
+/// with the number of bytes they take up.
+fn parse_frames(version: u8, tag_flags: u8, body: &[u8]) -> (Vec<Frame>, usize) {
+  let (id_size, header_size) = if version == 2 { (3, 6) } else { (4, 10) };
+  let mut frames = Vec::new();
+  let mut position = 0;
+
+  while position + header_size <= body.len() {
+    let header = &body[position..position + header_size];
+
+    if !header[..id_size].iter().all(|&b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+      break;
+    }
+
+    let frame_size = match version {
+      2 => Some((header[3] as u32) << 16 | (header[4] as u32) << 8 | header[5] as u32),
+      3 => Some(read_u32(&header[4..8])),
+      _ => synchsafe(&header[4..8])
+    };
+
+    let start = position + header_size;
+    let end = match frame_size {
+      Some(s) if start + s as usize <= body.len() => start + s as usize,
+      _ => break
+    };
+
+    let id = String::from_utf8_lossy(&header[..id_size]).into_owned();
+    let id = if version == 2 { upgrade_id(&id).to_string() } else { id };
+    let format = if version == 2 { 0 } else { header[9] };
+
+    // The unsynchronisation of a whole ID3v2.4 tag is kept as that of the
+    // frame.
+    let kept = if version == 4 && tag_flags & UNSYNCHRONISATION != 0 { format | 0x02 } else { format };
+
+    frames.push(match frame_data(version, format, tag_flags, &body[start..end]) {
+      Some(data) => parse_frame(version, id, data),
+      None => Frame::Other { id, data: body[start..end].to_vec(), format: Some((version, kept)) }
+    });
+
+    position = end;
+  }
+
+  (frames, position)
+}
+
+/// Takes the data of a frame from what follows its header, leaving out the
+/// bytes that the format flags add in front of it and undoing the
+/// unsynchronisation of an ID3v2.4 frame. Compressed and encrypted frames
+/// cannot be read and give None.
+fn frame_data(version: u8, format: u8, tag_flags: u8, data: &[u8]) -> Option<Vec<u8>> {
+  let (unreadable, added, unsynchronised) = match version {
+    2 => (false, 0, false),
+    3 => (format & 0xC0 != 0, (format & 0x20 != 0) as usize, false),
+    _ => {
+      let added = (format & 0x40 != 0) as usize + (format & 0x04 != 0) as usize + 4 * (format & 0x01 != 0) as usize;
+
+      (format & 0x0C != 0, added, format & 0x02 != 0 || tag_flags & UNSYNCHRONISATION != 0)
+    }
+  };
+
+  if unreadable || added > data.len() {
+    return None;
+  }
+
+  Some(if unsynchronised { resynchronise(&data[added..]) } else { data[added..].to_vec() })
+}
+
+fn parse_frame(version: u8, id: String, data: Vec<u8>) -> Frame {
+  let frame = if data.is_empty() {
+    None
+  } else if id == "APIC" {
+    parse_picture(version, &data).map(Frame::Picture)
+  } else if id == "CHAP" {
+    parse_chapter(version, &data).map(Frame::Chapter)
+  } else if id == "CTOC" {
+    parse_table_of_contents(version, &data).map(Frame::TableOfContents)
+  } else if id == "TXXX" || id == "WXXX" {
+    let (description, rest) = take_string(data[0], &data[1..]);
+
+    if id == "TXXX" {
+      Some(Frame::UserText { description, value: take_string(data[0], rest).0 })
+    } else {
+      Some(Frame::UserUrl { description, url: take_string(0, rest).0 })
+    }
+  } else if id == "COMM" && data.len() >= 4 {
+    let (description, rest) = take_string(data[0], &data[4..]);
+
+    Some(Frame::Comment {
+      language: String::from_utf8_lossy(&data[1..4]).into_owned(),
+      description,
+      text: take_string(data[0], rest).0
+    })
+  } else if id.starts_with("T") {
+    Some(Frame::Text { id: id.clone(), values: split_strings(data[0], &data[1..]) })
+  } else if id.starts_with("W") {
+    Some(Frame::Url { id: id.clone(), url: take_string(0, &data).0 })
+  } else {
+    None
+  };
+
+  match frame {
+    Some(f) => f,
+    None => Frame::Other { id, data, format: None }
+  }
+}
+
+/// Reads an APIC frame, or the PIC frame of ID3v2.2 that gives the image
+/// format rather than its MIME type.
+fn parse_picture(version: u8, data: &[u8]) -> Option<Picture> {
+  let (mime_type, rest) = if version == 2 {
+    if data.len() < 4 {
+      return None;
+    }
+
+    let format = String::from_utf8_lossy(&data[1..4]).to_lowercase();
+
+    (if format == "jpg" { "image/jpeg".to_string() } else { format!("image/{}", format) }, &data[4..])
+  } else {
+    take_string(0, &data[1..])
+  };
+
+  if rest.is_empty() {
+    return None;
+  }
+
+  let (description, picture) = take_string(data[0], &rest[1..]);
+
+  Some(Picture { mime_type, picture_type: rest[0], description, data: picture.to_vec() })
+}
+
+/// Reads a CHAP frame. Its frames are not unsynchronised on their own, as
+/// the frame holding them is already.
+fn parse_chapter(version: u8, data: &[u8]) -> Option<Chapter> {
+  let (element_id, rest) = take_string(0, data);
+
+  if rest.len() < 16 {
+    return None;
+  }
+
+  let offset = |value: u32| if value == NO_OFFSET { None } else { Some(value) };
+
+  Some(Chapter {
+    element_id,
+    start_time: read_u32(&rest[0..4]),
+    end_time: read_u32(&rest[4..8]),
+    start_offset: offset(read_u32(&rest[8..12])),
+    end_offset: offset(read_u32(&rest[12..16])),
+    frames: parse_frames(version, 0, &rest[16..]).0
+  })
+}
+
+fn parse_table_of_contents(version: u8, data: &[u8]) -> Option<TableOfContents> {
+  let (element_id, rest) = take_string(0, data);
+
+  if rest.len() < 2 {
+    return None;
+  }
+
+  let mut children = Vec::new();
+  let mut entries = &rest[2..];
+
+  for _ in 0..rest[1] {
+    let (child, r) = take_string(0, entries);
+
+    children.push(child);
+    entries = r;
+  }
+
+  Some(TableOfContents {
+    element_id,
+    top_level: rest[0] & TOP_LEVEL != 0,
+    ordered: rest[0] & ORDERED != 0,
+    children,
+    frames: parse_frames(version, 0, entries).0
+  })
+}
+
+/// Splits the strings in `encoding` that make up the value of a text frame.
+/// A terminator after the last string is left out.
+fn split_strings(encoding: u8, data: &[u8]) -> Vec<String> {
+  let mut strings = Vec::new();
+  let mut rest = data;
+
+  while !rest.is_empty() {
+    let (s, r) = take_string(encoding, rest);
+
+    strings.push(s);
+    rest = r;
+  }
+
+  strings
+}
+
+/// Reads the string in `encoding` that starts `data`, up to its terminator
+/// or the end of `data`, and returns it along with what follows it. The
+/// encodings are ISO-8859-1, UTF-16 with a byte order mark, UTF-16BE and
+/// UTF-8. Anything else is read as ISO-8859-1.
+fn take_string(encoding: u8, data: &[u8]) -> (String, &[u8]) {
+  let width = if encoding == 1 || encoding == 2 { 2 } else { 1 };
+  let mut end = 0;
+
+  while end + width <= data.len() && data[end..end + width].iter().any(|&b| b != 0) {
+    end += width;
+  }
+
+  let bytes = &data[..end];
+  let rest = &data[(end + width).min(data.len())..];
+
+  let string = match encoding {
+    1 | 2 => {
+      let (big_endian, bytes) = if bytes.starts_with(&[0xFF, 0xFE]) {
+        (false, &bytes[2..])
+      } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        (true, &bytes[2..])
+      } else {
+        (true, bytes)
+      };
+
+      let units: Vec<u16> = bytes.chunks(2).filter(|c| c.len() == 2).map(|c| {
+        if big_endian { (c[0] as u16) << 8 | c[1] as u16 } else { (c[1] as u16) << 8 | c[0] as u16 }
+      }).collect();
+
+      String::from_utf16_lossy(&units)
+    },
+    3 => String::from_utf8_lossy(bytes).into_owned(),
+    _ => bytes.iter().map(|&b| b as char).collect()
+  };
+
+  (string, rest)
+}
+
+/// Undoes unsynchronisation, which puts a zero byte after every 0xFF byte
+/// that could be taken for the start of a sync word.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+  let mut result = Vec::with_capacity(data.len());
+
+  for (i, &b) in data.iter().enumerate() {
+    if b == 0 && i > 0 && data[i - 1] == 0xFF {
+      continue;
+    }
+
+    result.push(b);
+  }
+
+  result
+}
+
+/// Reads a synchsafe integer, which keeps the top bit of each byte clear.
+fn synchsafe(bytes: &[u8]) -> Option<u32> {
+  let mut value = 0u32;
+
+  for &b in bytes {
+    if b & 0x80 != 0 {
+      return None;
+    }
+
+    value = value << 7 | b as u32;
+  }
+
+  Some(value)
+}
+
+/// The index of the sample at `time` milliseconds, to the nearest sample.
+fn time_to_sample(time: u32, samplerate: u32) -> u64 {
+  (time as u64 * samplerate as u64 + 500) / 1000
+}
+
+fn to_synchsafe(value: u32) -> [u8; 4] {
+  [(value >> 21) as u8 & 0x7F, (value >> 14) as u8 & 0x7F, (value >> 7) as u8 & 0x7F, value as u8 & 0x7F]
+}
+
+fn to_u32(value: u32) -> [u8; 4] {
+  [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+/// The ID3v2.3 frame ID of an ID3v2.2 frame ID, for the frames that have
+/// one.
+fn upgrade_id(id: &str) -> &str {
+  match id {
+    "TAL" => "TALB", "TBP" => "TBPM", "TCM" => "TCOM", "TCO" => "TCON",
+    "TCR" => "TCOP", "TEN" => "TENC", "TLE" => "TLEN", "TP1" => "TPE1",
+    "TP2" => "TPE2", "TP3" => "TPE3", "TPA" => "TPOS", "TPB" => "TPUB",
+    "TRK" => "TRCK", "TSS" => "TSSE", "TT1" => "TIT1", "TT2" => "TIT2",
+    "TT3" => "TIT3", "TXT" => "TEXT", "TXX" => "TXXX", "TYE" => "TYER",
+    "COM" => "COMM", "PIC" => "APIC", "ULT" => "USLT", "WAF" => "WOAF",
+    "WAR" => "WOAR", "WAS" => "WOAS", "WCM" => "WCOM", "WCP" => "WCOP",
+    "WPB" => "WPUB", "WXX" => "WXXX",
+    _ => id
+  }
+}
+
+#[cfg(test)]
+fn tag_header(version: u8, flags: u8, size: usize) -> Vec<u8> {
+  vec![b'I', b'D', b'3', version, 0, flags, 0, 0, (size >> 7) as u8, (size & 0x7F) as u8]
+}
+
+#[cfg(test)]
+fn frame(id: &str, data: &[u8]) -> Vec<u8> {
+  let mut frame = id.as_bytes().to_vec();
+
+  frame.extend(&[0, 0, 0, data.len() as u8, 0, 0]);
+  frame.extend(data);
+
+  frame
+}
+
+#[test]
+fn test_parse() {
+  let mut frames = frame("TIT2", b"\x00Title\x00");
+
+  frames.extend(frame("TPE1", b"\x01\xFF\xFEA\x00b\x00\x00\x00\xFE\xFF\x00C"));
+  frames.extend(frame("COMM", b"\x03engdesc\x00\xC3\xA9t\xC3\xA9"));
+  frames.extend(frame("TXXX", b"\x00key\x00value"));
+  frames.extend(frame("WOAR", b"http://example.com"));
+  frames.extend(frame("WXXX", b"\x00\x00http://example.org"));
+  frames.extend(frame("PRIV", b"owner\x00\x01"));
+
+  let mut data = tag_header(4, 0, frames.len() + 20);
+
+  data.extend(frames);
+  data.extend(&[0u8; 20]);
+
+  assert_eq!(tag_size(&data), Some(data.len()));
+
+  let tag = parse(&data).unwrap();
+
+  assert_eq!((tag.version, tag.revision, tag.padding), (4, 0, 20));
+  assert_eq!(tag.text("TIT2"), Some("Title"));
+  assert_eq!(tag.frames[1], Frame::Text { id: "TPE1".to_string(), values: vec!["Ab".to_string(), "C".to_string()] });
+  assert_eq!(tag.frames[2], Frame::Comment { language: "eng".to_string(), description: "desc".to_string(), text: "été".to_string() });
+  assert_eq!(tag.frames[3], Frame::UserText { description: "key".to_string(), value: "value".to_string() });
+  assert_eq!(tag.frames[4], Frame::Url { id: "WOAR".to_string(), url: "http://example.com".to_string() });
+  assert_eq!(tag.frames[5], Frame::UserUrl { description: "".to_string(), url: "http://example.org".to_string() });
+  assert_eq!(tag.frames[6], Frame::Other { id: "PRIV".to_string(), data: b"owner\x00\x01".to_vec(), format: None });
+}
+
+#[test]
+fn test_parse_versions() {
+  // An unsynchronised ID3v2.3 tag with an extended header, where the frame
+  // size is that of the frame data once resynchronised.
+  let mut data = tag_header(3, UNSYNCHRONISATION | EXTENDED_HEADER, 24);
+
+  data.extend(&[0, 0, 0, 6, 0, 0, 0, 0, 0, 0]);
+  data.extend(b"TIT2\x00\x00\x00\x03\x00\x00\x00\xFF\x00\xE0");
+
+  let tag = parse(&data).unwrap();
+
+  assert_eq!(tag.frames, vec![Frame::Text { id: "TIT2".to_string(), values: vec!["\u{FF}\u{E0}".to_string()] }]);
+
+  // A grouped ID3v2.3 frame, whose data starts with the group.
+  let mut data = tag_header(3, 0, 17);
+
+  data.extend(b"TIT2\x00\x00\x00\x07\x00\x20\x01\x00Title");
+
+  assert_eq!(parse(&data).unwrap().text("TIT2"), Some("Title"));
+
+  // An ID3v2.2 tag, and an ID3v2.4 tag with a footer.
+  let mut data = tag_header(2, 0, 12);
+
+  data.extend(b"TT2\x00\x00\x06\x00Title");
+
+  assert_eq!(parse(&data).unwrap().text("TIT2"), Some("Title"));
+
+  let mut data = tag_header(4, FOOTER, 0);
+
+  data.extend(&[b'3', b'D', b'I', 4, 0, FOOTER, 0, 0, 0, 0]);
+
+  assert_eq!(tag_size(&data), Some(20));
+  assert_eq!(parse(&data).unwrap().frames, vec![]);
+  assert!(tag_size(b"ID3\x04\x00\x00\x00\x00\x80\x00").is_none());
+}
+
+#[test]
+fn test_write() {
+  let mut tag = Tag::new(4);
+
+  tag.set_text("TIT2", vec!["Title".to_string(), "Титул".to_string()]);
+  tag.frames.push(Frame::Comment { language: "eng".to_string(), description: "".to_string(), text: "Comment".to_string() });
+  tag.frames.push(Frame::UserText { description: "key".to_string(), value: "value".to_string() });
+  tag.frames.push(Frame::Url { id: "WOAR".to_string(), url: "http://example.com".to_string() });
+  tag.frames.push(Frame::UserUrl { description: "été".to_string(), url: "http://example.org".to_string() });
+  tag.frames.push(Frame::Other { id: "PRIV".to_string(), data: b"owner\x00\xFF\x00".to_vec(), format: None });
+  tag.padding = 100;
+
+  let data = tag.to_bytes();
+
+  tag.size = data.len();
+  assert_eq!(parse(&data).unwrap(), tag);
+
+  // ID3v2.3 has UTF-16 rather than UTF-8, and a single value.
+  tag.version = 3;
+  tag.frames.truncate(1);
+  tag.set_text("TIT2", vec!["Титул".to_string()]);
+
+  let data = tag.to_bytes();
+
+  tag.size = data.len();
+  assert_eq!(data[20], 1);
+  assert_eq!(parse(&data).unwrap(), tag);
+}
+
+#[test]
+fn test_write_compressed_frame() {
+  // A compressed frame with a data length indicator, which is kept as it was
+  // and written back with its format flags.
+  let mut frames = frame("TIT2", b"\x00Title");
+
+  frames.extend(b"TPE1\x00\x00\x00\x08\x00\x09\x00\x00\x00\x07\x78\x9C\x03\x00");
+
+  let mut data = tag_header(4, 0, frames.len());
+
+  data.extend(frames);
+
+  let mut tag = parse(&data).unwrap();
+
+  assert_eq!(tag.frames[1], Frame::Other { id: "TPE1".to_string(), data: b"\x00\x00\x00\x07\x78\x9C\x03\x00".to_vec(), format: Some((4, 0x09)) });
+
+  tag.padding = 0;
+
+  let written = tag.to_bytes();
+
+  assert_eq!(&written[26..], &data[26..]);
+  assert_eq!(parse(&written).unwrap().frames, tag.frames);
+
+  // In ID3v2.3 the flags would mean something else.
+  tag.version = 3;
+
+  assert_eq!(parse(&tag.to_bytes()).unwrap().frames, vec![tag.frames[0].clone()]);
+}
+
+#[test]
+fn test_write_tag() {
+  // A tag with room to spare in front of frames that must stay as they are:
+  // mono Layer I frames of 672 bytes allocating four bits to every subband,
+  // with scale factors and samples from a pseudo random sequence.
+  let mut tag = Tag::new(3);
+
+  tag.set_text("TIT2", vec!["Title".to_string()]);
+  tag.padding = 20;
+
+  let mut frames = vec![0u8; 672 * 6];
+  let mut seed = 1u32;
+
+  for frame in frames.chunks_mut(672) {
+    frame[..4].copy_from_slice(&[0xFF, 0xFF, 0xE8, 0xC0]);
+
+    for byte in frame[4..20].iter_mut() {
+      *byte = 0x33;
+    }
+
+    for byte in frame[20..236].iter_mut() {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      *byte = (seed >> 16) as u8;
+    }
+  }
+
+  // The frames are found where the tag puts them and decode to the same
+  // samples.
+  let decode = |data: &[u8]| -> Vec<(u64, Vec<Vec<f64>>)> {
+    let mut decoder = decoder::Decoder::new(data);
+    let mut decoded = Vec::new();
+
+    while let Some(mut frame) = decoder.next_frame().unwrap() {
+      let pcm = decoder.decode_frame(&mut frame).unwrap();
+
+      decoded.push((frame.offset, pcm));
+    }
+
+    decoded
+  };
+
+  let original = decode(&frames);
+  let shifted = |size: u64| -> Vec<(u64, Vec<Vec<f64>>)> {
+    original.iter().map(|&(offset, ref pcm)| (offset + size, pcm.clone())).collect()
+  };
+
+  assert_eq!(original.len(), 6);
+  assert!(original.iter().all(|(_, pcm)| pcm[0].iter().any(|&sample| sample != 0.0)));
+
+  let mut file = io::Cursor::new(tag.to_bytes());
+
+  file.get_mut().extend(&frames);
+
+  // A tag that fits is written in place.
+  tag.set_text("TPE1", vec!["Artist".to_string()]);
+  write_tag(&mut file, &tag).unwrap();
+
+  let written = parse(file.get_ref()).unwrap();
+
+  assert_eq!((written.size, written.padding), (46, 3));
+  assert_eq!(written.text("TPE1"), Some("Artist"));
+  assert_eq!(&file.get_ref()[46..], &frames[..]);
+  assert_eq!(decode(file.get_ref()), shifted(46));
+
+  // One that does not moves the frames back.
+  tag.set_text("TALB", vec!["Album".to_string()]);
+  write_tag(&mut file, &tag).unwrap();
+
+  let written = parse(file.get_ref()).unwrap();
+
+  assert_eq!((written.size, written.padding), (1083, PADDING));
+  assert_eq!(written.frames, tag.frames);
+  assert_eq!(&file.get_ref()[1083..], &frames[..]);
+  assert_eq!(decode(file.get_ref()), shifted(1083));
+
+  // And so does a tag written to a file without one.
+  let mut file = io::Cursor::new(frames.clone());
+
+  write_tag(&mut file, &tag).unwrap();
+  assert_eq!(&file.get_ref()[1083..], &frames[..]);
+  assert_eq!(decode(file.get_ref()), shifted(1083));
+}
+
+#[test]
+fn test_chapters() {
+  let title = |t: &str| vec![Frame::Text { id: "TIT2".to_string(), values: vec![t.to_string()] }];
+  let mut tag = Tag::new(4);
+
+  tag.frames.push(Frame::Picture(Picture {
+    mime_type: "image/png".to_string(),
+    picture_type: 3,
+    description: "Cover".to_string(),
+    data: b"\x89PNG\x00\x00".to_vec()
+  }));
+  tag.frames.push(Frame::TableOfContents(TableOfContents {
+    element_id: "toc".to_string(),
+    top_level: true,
+    ordered: true,
+    children: vec!["ch1".to_string(), "ch0".to_string()],
+    frames: title("Contents")
+  }));
+
+  for &(id, start, end) in [("ch1", 1500, 3000), ("ch0", 0, 1500)].iter() {
+    tag.frames.push(Frame::Chapter(Chapter {
+      element_id: id.to_string(),
+      start_time: start,
+      end_time: end,
+      start_offset: if start > 0 { Some(start * 16) } else { None },
+      end_offset: None,
+      frames: title(id)
+    }));
+  }
+
+  let data = tag.to_bytes();
+
+  tag.size = data.len();
+
+  let parsed = parse(&data).unwrap();
+
+  assert_eq!(parsed, tag);
+  assert_eq!(parsed.pictures()[0].data, b"\x89PNG\x00\x00".to_vec());
+  assert_eq!(parsed.table_of_contents().unwrap().children, vec!["ch1".to_string(), "ch0".to_string()]);
+  assert_eq!(parsed.chapters().iter().map(|c| c.element_id.as_str()).collect::<Vec<&str>>(), vec!["ch0", "ch1"]);
+
+  let chapter = parsed.chapter("ch1").unwrap();
+
+  assert_eq!((chapter.start_sample(44100), chapter.end_sample(44100)), (66150, 132300));
+  assert_eq!(chapter.start_offset, Some(24000));
+  assert_eq!(Chapter { start_time: 1, ..chapter.clone() }.start_sample(22050), 22);
+
+  // The PIC frame of ID3v2.2 gives an image format.
+  let mut data = tag_header(2, 0, 13);
+
+  data.extend(b"PIC\x00\x00\x07\x00JPG\x04\x00\xFF");
+
+  assert_eq!(parse(&data).unwrap().pictures()[0], &Picture {
+    mime_type: "image/jpeg".to_string(),
+    picture_type: 4,
+    description: "".to_string(),
+    data: vec![0xFF]
+  });
+}
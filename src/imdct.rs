@@ -0,0 +1,147 @@
+use std::f64::consts::PI;
+
+/// The inverse modified discrete cosine transforms and windows of the Layer III
+/// hybrid filterbank (2.4.3.4.10.2 of ISO 11172-3).
+pub struct Imdct {
+  long: Box<[[f64; 18]; 36]>,
+  short: [[f64; 6]; 12],
+  windows: [[f64; 36]; 4]
+}
+
+impl Imdct {
+  pub fn new() -> Imdct {
+    let mut long = Box::new([[0f64; 18]; 36]);
+    let mut short = [[0f64; 6]; 12];
+    let mut windows = [[0f64; 36]; 4];
+
+    for (i, row) in long.iter_mut().enumerate() {
+      for (k, value) in row.iter_mut().enumerate() {
+        *value = (PI / 72.0 * (2 * i + 1 + 18) as f64 * (2 * k + 1) as f64).cos();
+      }
+    }
+
+    for (i, row) in short.iter_mut().enumerate() {
+      for (k, value) in row.iter_mut().enumerate() {
+        *value = (PI / 24.0 * (2 * i + 1 + 6) as f64 * (2 * k + 1) as f64).cos();
+      }
+    }
+
+    // Block type 0 (normal), 1 (start), 2 (short) and 3 (stop). The short
+    // window covers 12 samples and is applied to each of the three blocks.
+    for (i, value) in windows[0].iter_mut().enumerate() {
+      *value = (PI / 36.0 * (i as f64 + 0.5)).sin();
+    }
+
+    let normal = windows[0];
+
+    for (i, value) in windows[1].iter_mut().enumerate() {
+      *value = if i < 18 {
+        normal[i]
+      } else if i < 24 {
+        1.0
+      } else if i < 30 {
+        (PI / 12.0 * (i as f64 - 18.0 + 0.5)).sin()
+      } else {
+        0.0
+      };
+    }
+
+    for (i, value) in windows[3].iter_mut().enumerate() {
+      *value = if i < 6 {
+        0.0
+      } else if i < 12 {
+        (PI / 12.0 * (i as f64 - 6.0 + 0.5)).sin()
+      } else if i < 18 {
+        1.0
+      } else {
+        normal[i]
+      };
+    }
+
+    for (i, value) in windows[2][..12].iter_mut().enumerate() {
+      *value = (PI / 12.0 * (i as f64 + 0.5)).sin();
+    }
+
+    Imdct { long, short, windows }
+  }
+
+  /// Transforms the 18 frequency lines of one subband into 36 windowed time
+  /// samples. For short blocks the lines are interleaved by window, and the
+  /// three transformed windows overlap inside the output.
+  pub fn transform(&self, input: &[f64], block_type: u32, output: &mut [f64; 36]) {
+    if block_type == 2 {
+      *output = [0f64; 36];
+
+      for window in 0..3 {
+        for (i, row) in self.short.iter().enumerate() {
+          let mut sum = 0f64;
+
+          for (k, c) in row.iter().enumerate() {
+            sum += input[3 * k + window] * c;
+          }
+
+          output[6 + 6 * window + i] += sum * self.windows[2][i];
+        }
+      }
+
+      return;
+    }
+
+    let window = &self.windows[block_type as usize];
+
+    for (i, row) in self.long.iter().enumerate() {
+      let mut sum = 0f64;
+
+      for (x, c) in input.iter().zip(row.iter()) {
+        sum += x * c;
+      }
+
+      output[i] = sum * window[i];
+    }
+  }
+}
+
+#[test]
+fn test_silence() {
+  let imdct = Imdct::new();
+  let mut output = [1f64; 36];
+
+  for block_type in 0..4 {
+    imdct.transform(&[0f64; 18], block_type, &mut output);
+
+    for &sample in output.iter() {
+      assert_eq!(sample, 0.0);
+    }
+  }
+}
+
+#[test]
+fn test_long_block_time_domain_aliasing() {
+  // The first half of an IMDCT output is odd symmetric and the second half
+  // even symmetric, which is what lets consecutive blocks cancel out.
+  let imdct = Imdct::new();
+  let mut input = [0f64; 18];
+  let mut output = [0f64; 36];
+
+  input[3] = 1.0;
+  input[10] = -0.5;
+
+  imdct.transform(&input, 0, &mut output);
+
+  let window = &imdct.windows[0];
+
+  for i in 0..9 {
+    assert!((output[i] / window[i] + output[17 - i] / window[17 - i]).abs() < 1e-9);
+    assert!((output[18 + i] / window[18 + i] - output[35 - i] / window[35 - i]).abs() < 1e-9);
+  }
+}
+
+#[test]
+fn test_start_and_stop_windows() {
+  let imdct = Imdct::new();
+
+  // The stop window is the start window reversed.
+  for i in 0..36 {
+    assert!((imdct.windows[1][i] - imdct.windows[3][35 - i]).abs() < 1e-12);
+  }
+}
@@ -1,11 +1,11 @@
 use std::io;
 use std::io::Cursor;
-use std::fs;
 
 use bitreader;
+use error;
 use header;
 
-static SCALE_FACTORS_TABLE: [f64; 64] = [
+pub static SCALE_FACTORS_TABLE: [f64; 64] = [
   2.000000000000, 1.587401051968, 1.259921049895, 1.000000000000,
   0.793700525984, 0.629960524947, 0.500000000000, 0.396850262992,
   0.314980262474, 0.250000000000, 0.198425131496, 0.157490131237,
@@ -32,80 +32,135 @@ static LINEAR_SCALING_TABLE: [f64; 14] = [
   1.00006103888177, 1.00003051850948
 ];
 
-pub fn decode_layer1(reader: &mut fs::File, frame_header: header::Header) -> Box<[[[f64; 32]; 12]; 2]> {
-  let mut bit_reader = bitreader::BitReader::new(reader);
+/// The subband samples of a frame, by channel and sample.
+pub type SubbandSamples = [[[f64; 32]; 12]; 2];
+
+/// Decodes the payload of a frame, returning its subband samples and the
+/// ancillary data after them.
+pub fn decode_layer1(payload: &[u8], frame_header: &header::Header) -> error::Result<(Box<SubbandSamples>, Vec<u8>)> {
+  let mut reader = Cursor::new(payload);
+  let mut bit_reader = bitreader::BitReader::new(&mut reader);
   let nb_subbands = 32;
-  let num_channels = if frame_header.channel_mode != 3 { 2 } else { 1 };
+  let num_channels = frame_header.num_channels();
+  let bound = frame_header.intensity_bound();
 
-  let allocations = decode_bit_allocations(&mut bit_reader, nb_subbands, num_channels);
-  let scale_factors = decode_scale_factors(&mut bit_reader, nb_subbands, num_channels, &allocations);
-  let samples = decode_samples(&mut bit_reader, nb_subbands, num_channels, &allocations, &scale_factors);
+  let allocations = decode_bit_allocations(&mut bit_reader, nb_subbands, num_channels, bound)?;
+  let scale_factors = decode_scale_factors(&mut bit_reader, nb_subbands, num_channels, &allocations)?;
+  let samples = decode_samples(&mut bit_reader, nb_subbands, num_channels, bound, &allocations, &scale_factors)?;
 
-  samples
+  match read_ancillary_data(&mut bit_reader, payload.len()) {
+    Ok(ancillary) => Ok((samples, ancillary)),
+    Err(e) => Err(e)
+  }
 }
 
-fn decode_bit_allocations(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize) -> Box<[[u32; 32]; 2]> {
+/// Reads the rest of a Layer I or II payload of `length` bytes after its
+/// samples, which is ancillary data. The last byte is padded with zero bits
+/// when the data does not end on a byte boundary.
+pub fn read_ancillary_data(bit_reader: &mut bitreader::BitReader, length: usize) -> error::Result<Vec<u8>> {
+  let end = length as u64 * 8;
+
+  if bit_reader.position() > end {
+    return Err(error::Error::FrameOverrun(None));
+  }
+
+  let mut remaining = end - bit_reader.position();
+  let mut ancillary = Vec::with_capacity((remaining as usize).div_ceil(8));
+
+  while remaining > 0 {
+    let n = if remaining < 8 { remaining as u32 } else { 8 };
+    let bits = bit_reader.read_bits(n)?;
+
+    ancillary.push((bits << (8 - n)) as u8);
+    remaining -= n as u64;
+  }
+
+  Ok(ancillary)
+}
+
+/// The number of bits after the CRC check word that the check covers, which
+/// are those of the bit allocations.
+pub fn protected_bits(frame_header: &header::Header) -> usize {
+  let bound = frame_header.intensity_bound();
+
+  4 * (bound * frame_header.num_channels() + (32 - bound))
+}
+
+fn decode_bit_allocations(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize, bound: usize) -> error::Result<Box<[[u32; 32]; 2]>> {
   let mut allocations = Box::new([[0u32; 32]; 2]);
 
   for subband in 0..num_subbands {
-    for channel in 0..num_channels {
-      let g = bit_reader.read_bits(4);
+    // Above the bound both channels share a single allocation.
+    if subband >= bound {
+      allocations[0][subband] = bit_reader.read_bits(4)?;
+      allocations[1][subband] = allocations[0][subband];
+      continue;
+    }
 
-      allocations[channel][subband] = g.unwrap();
+    for channel in 0..num_channels {
+      allocations[channel][subband] = bit_reader.read_bits(4)?;
     }
   }
 
-  return allocations;
+  Ok(allocations)
 }
 
-fn decode_scale_factors(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize, allocations: &Box<[[u32; 32]; 2]>) -> Box<[[u32; 32]; 2]> {
+fn decode_scale_factors(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize, allocations: &[[u32; 32]; 2]) -> error::Result<Box<[[u32; 32]; 2]>> {
   let mut scale_factors = Box::new([[0u32; 32]; 2]);
 
   for subband in 0..num_subbands {
     for channel in 0..num_channels {
-      let factor = if allocations[channel][subband] != 0 { bit_reader.read_bits(6).unwrap() } else { 0 };
-
-      scale_factors[channel][subband] = factor;
+      if allocations[channel][subband] != 0 {
+        scale_factors[channel][subband] = bit_reader.read_bits(6)?;
+      }
     }
   }
 
-  return scale_factors;
+  Ok(scale_factors)
 }
 
-fn decode_samples(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize, allocations: &Box<[[u32; 32]; 2]>, scale_factors: &Box<[[u32; 32]; 2]>) -> Box<[[[f64; 32]; 12]; 2]> {
+fn decode_samples(bit_reader: &mut bitreader::BitReader, num_subbands: usize, num_channels: usize, bound: usize, allocations: &[[u32; 32]; 2], scale_factors: &[[u32; 32]; 2]) -> error::Result<Box<[[[f64; 32]; 12]; 2]>> {
   let mut samples = Box::new([[[0f64; 32]; 12]; 2]);
 
   for sample in 0..12 {
     for subband in 0..num_subbands {
-      for channel in 0..num_channels {
-        let nb = allocations[channel][subband];
+      let coded_channels = if subband >= bound { 1 } else { num_channels };
 
-        samples[channel][sample][subband] = if nb > 0 {
-          calculate_sample(bit_reader, nb as usize) * SCALE_FACTORS_TABLE[scale_factors[channel][subband] as usize]
+      for (channel, channel_allocations) in allocations.iter().enumerate().take(coded_channels) {
+        let nb = channel_allocations[subband];
+
+        // An allocation of 15 is forbidden, so it is treated as silence.
+        let value = if nb > 0 && nb < 15 {
+          calculate_sample(bit_reader, nb as usize + 1)?
         } else {
           0.0
         };
+
+        // Samples above the bound are shared, each channel applying its own
+        // scale factor.
+        let channels = if subband >= bound { 0..num_channels } else { channel..channel + 1 };
+
+        for output_channel in channels {
+          samples[output_channel][sample][subband] = value * SCALE_FACTORS_TABLE[scale_factors[output_channel][subband] as usize];
+        }
       }
     }
   }
 
-  return samples;
+  Ok(samples)
 }
 
-fn calculate_sample(bit_reader: &mut bitreader::BitReader, nb: usize) -> f64 {
+fn calculate_sample(bit_reader: &mut bitreader::BitReader, nb: usize) -> error::Result<f64> {
   match bit_reader.read_bits(nb as u32) {
     Ok(s) => {
-      let sample = (s as f64) / ((1u64 << nb) as f64) - 0.5;
+      // Inverting the most significant bit gives a two's complement
+      // fraction, which is then offset by 2^(-nb + 1) (2.4.3.2).
+      let fraction = (s as f64) / ((1u64 << (nb - 1)) as f64) - 1.0;
+      let sample = fraction + 1.0 / ((1u64 << (nb - 1)) as f64);
 
-      let table = if nb == 0 {
-        LINEAR_SCALING_TABLE[0]
-      } else {
-        LINEAR_SCALING_TABLE[nb - 1]
-      };
-
-      return (sample as f64) * table;
+      Ok(sample * LINEAR_SCALING_TABLE[nb - 2])
     },
-    Err(_) => 0.0
+    Err(e) => Err(e)
   }
 }
 
@@ -115,7 +170,7 @@ fn generate_test_allocations() -> Box<[[u32; 32]; 2]> {
   let mut br = io::BufReader::new(buf);
   let mut r = bitreader::BitReader::new(&mut br);
 
-  return decode_bit_allocations(&mut r, 32, 2);
+  return decode_bit_allocations(&mut r, 32, 2, 32).unwrap();
 }
 
 #[test]
@@ -189,6 +244,25 @@ fn test_bit_allocations() {
   assert_eq!(samples[1][31], 0x0);
 }
 
+#[test]
+fn test_joint_stereo_bit_allocations() {
+  let mut buf = vec![0x12, 0x34, 0x56, 0x78, 0x9A];
+  buf.extend(vec![0u8; 13]);
+
+  let mut br = io::BufReader::new(Cursor::new(buf));
+  let mut r = bitreader::BitReader::new(&mut br);
+
+  let allocations = decode_bit_allocations(&mut r, 32, 2, 4).unwrap();
+
+  assert_eq!(allocations[0][3], 0x7);
+  assert_eq!(allocations[1][3], 0x8);
+  assert_eq!(allocations[0][4], 0x9);
+  assert_eq!(allocations[1][4], 0x9);
+  assert_eq!(allocations[0][5], 0xA);
+  assert_eq!(allocations[1][5], 0xA);
+  assert_eq!(r.position(), 4 * 8 + 4 * 28);
+}
+
 #[test]
 fn test_scale_factors() {
   let allocations = generate_test_allocations();
@@ -196,7 +270,7 @@ fn test_scale_factors() {
   let mut br = io::BufReader::new(buf);
   let mut r = bitreader::BitReader::new(&mut br);
 
-  let samples = decode_scale_factors(&mut r, 32, 2, &allocations);
+  let samples = decode_scale_factors(&mut r, 32, 2, &allocations).unwrap();
 
   assert_eq!(samples[0][0], 12);
   assert_eq!(samples[1][0], 12);
@@ -263,3 +337,48 @@ fn test_scale_factors() {
   assert_eq!(samples[0][31], 0);
   assert_eq!(samples[1][31], 0);
 }
+
+#[test]
+fn test_calculate_sample() {
+  let buf = Cursor::new(vec![0x18, 0x00]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+
+  assert!((calculate_sample(&mut r, 2).unwrap() + 2.0 / 3.0).abs() < 1e-12);
+  assert!((calculate_sample(&mut r, 2).unwrap() - 0.0).abs() < 1e-12);
+  assert!((calculate_sample(&mut r, 2).unwrap() - 2.0 / 3.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_ancillary_data() {
+  // The 44 bytes following the header of a 32 kbit/s mono frame at 32 kHz,
+  // of which the samples took 338 bits.
+  let mut data = vec![0u8; 44];
+
+  data[42] = 0xAB;
+  data[43] = 0xCD;
+
+  let mut reader = Cursor::new(data);
+  let mut br = bitreader::BitReader::new(&mut reader);
+
+  br.skip_bits(338).unwrap();
+
+  let ancillary = read_ancillary_data(&mut br, 44).unwrap();
+
+  assert_eq!(ancillary, vec![0xAF, 0x34]);
+  assert_eq!(br.position(), 44 * 8);
+}
+
+#[test]
+fn test_payload_overrun() {
+  // Every subband of a mono frame is allocated 15 bit samples, which do not
+  // fit in 48 bytes.
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFF18C0)).unwrap();
+  let payload = vec![0xEEu8; 44];
+
+  match decode_layer1(&payload, &frame_header) {
+    Err(error::Error::FrameOverrun(_)) => {},
+    _ => panic!("decoded past the end of the payload")
+  };
+}
+
@@ -0,0 +1,331 @@
+use std::io::Cursor;
+
+use bitreader;
+use error;
+use header;
+use header::MpegVersion::*;
+use layer1;
+use layer1::SCALE_FACTORS_TABLE;
+
+struct QuantizationClass {
+  levels: u32,
+  grouping: bool,
+  bits: u32,
+  c: f64,
+  d: f64
+}
+
+/// Classes of quantization from table 3-B.4 of ISO 11172-3. With grouping,
+/// `bits` is the size of the codeword holding three samples.
+static QUANTIZATION_CLASSES: [QuantizationClass; 17] = [
+  QuantizationClass { levels: 3, grouping: true, bits: 5, c: 1.33333333333, d: 0.50000000000 },
+  QuantizationClass { levels: 5, grouping: true, bits: 7, c: 1.60000000000, d: 0.50000000000 },
+  QuantizationClass { levels: 7, grouping: false, bits: 3, c: 1.14285714286, d: 0.25000000000 },
+  QuantizationClass { levels: 9, grouping: true, bits: 10, c: 1.77777777777, d: 0.50000000000 },
+  QuantizationClass { levels: 15, grouping: false, bits: 4, c: 1.06666666666, d: 0.12500000000 },
+  QuantizationClass { levels: 31, grouping: false, bits: 5, c: 1.03225806452, d: 0.06250000000 },
+  QuantizationClass { levels: 63, grouping: false, bits: 6, c: 1.01587301587, d: 0.03125000000 },
+  QuantizationClass { levels: 127, grouping: false, bits: 7, c: 1.00787401575, d: 0.01562500000 },
+  QuantizationClass { levels: 255, grouping: false, bits: 8, c: 1.00392156863, d: 0.00781250000 },
+  QuantizationClass { levels: 511, grouping: false, bits: 9, c: 1.00195694716, d: 0.00390625000 },
+  QuantizationClass { levels: 1023, grouping: false, bits: 10, c: 1.00097751711, d: 0.00195312500 },
+  QuantizationClass { levels: 2047, grouping: false, bits: 11, c: 1.00048851979, d: 0.00097656250 },
+  QuantizationClass { levels: 4095, grouping: false, bits: 12, c: 1.00024420024, d: 0.00048828125 },
+  QuantizationClass { levels: 8191, grouping: false, bits: 13, c: 1.00012208522, d: 0.00024414063 },
+  QuantizationClass { levels: 16383, grouping: false, bits: 14, c: 1.00006103888, d: 0.00012207031 },
+  QuantizationClass { levels: 32767, grouping: false, bits: 15, c: 1.00003051851, d: 0.00006103516 },
+  QuantizationClass { levels: 65535, grouping: false, bits: 16, c: 1.00001525902, d: 0.00003051758 }
+];
+
+/// The number of allocation bits of a subband and the index into
+/// `QUANTIZATION_CLASSES` for each allocation value. Allocation 0 means that
+/// nothing is transmitted for the subband.
+struct SubbandAllocation {
+  nbal: u32,
+  classes: [usize; 16]
+}
+
+static SUBBAND_ALLOCATIONS: [SubbandAllocation; 8] = [
+  SubbandAllocation { nbal: 4, classes: [0, 0, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16] },
+  SubbandAllocation { nbal: 4, classes: [0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 16] },
+  SubbandAllocation { nbal: 3, classes: [0, 0, 1, 2, 3, 4, 5, 16, 0, 0, 0, 0, 0, 0, 0, 0] },
+  SubbandAllocation { nbal: 2, classes: [0, 0, 1, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] },
+  SubbandAllocation { nbal: 4, classes: [0, 0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15] },
+  SubbandAllocation { nbal: 3, classes: [0, 0, 1, 3, 4, 5, 6, 7, 0, 0, 0, 0, 0, 0, 0, 0] },
+  SubbandAllocation { nbal: 4, classes: [0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14] },
+  SubbandAllocation { nbal: 2, classes: [0, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] }
+];
+
+/// A bit allocation table: the number of coded subbands and the index into
+/// `SUBBAND_ALLOCATIONS` for each of them.
+struct AllocationTable {
+  sblimit: usize,
+  subbands: [usize; 32]
+}
+
+/// Tables 3-B.2a to 3-B.2d of ISO 11172-3, and table B.1 of ISO 13818-3 for
+/// the lower sampling frequencies.
+static ALLOCATION_TABLES: [AllocationTable; 5] = [
+  AllocationTable {
+    sblimit: 27,
+    subbands: [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 0, 0, 0, 0, 0]
+  },
+  AllocationTable {
+    sblimit: 30,
+    subbands: [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 0, 0]
+  },
+  AllocationTable {
+    sblimit: 8,
+    subbands: [4, 4, 5, 5, 5, 5, 5, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+  },
+  AllocationTable {
+    sblimit: 12,
+    subbands: [4, 4, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+  },
+  AllocationTable {
+    sblimit: 30,
+    subbands: [6, 6, 6, 6, 5, 5, 5, 5, 5, 5, 5, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 0, 0]
+  }
+];
+
+fn select_allocation_table(frame_header: &header::Header) -> &'static AllocationTable {
+  match frame_header.version {
+    MPEG1_0 => {},
+    _ => return &ALLOCATION_TABLES[4]
+  }
+
+  let samplerate = frame_header.samplerate.unwrap_or(44100);
+
+  // Without a bitrate (free format) the high bitrate tables are used.
+  let bitrate_per_channel = match frame_header.bitrate {
+    Some(b) => b / frame_header.num_channels() as u32,
+    None => return &ALLOCATION_TABLES[if samplerate == 48000 { 0 } else { 1 }]
+  };
+
+  if bitrate_per_channel <= 48 {
+    &ALLOCATION_TABLES[if samplerate == 32000 { 3 } else { 2 }]
+  } else if bitrate_per_channel <= 80 {
+    &ALLOCATION_TABLES[0]
+  } else {
+    &ALLOCATION_TABLES[if samplerate == 48000 { 0 } else { 1 }]
+  }
+}
+
+/// The subband samples of a frame, by channel and sample.
+pub type SubbandSamples = [[[f64; 32]; 36]; 2];
+
+/// Decodes the payload of a frame, returning its subband samples and the
+/// ancillary data after them.
+pub fn decode_layer2(payload: &[u8], frame_header: &header::Header) -> error::Result<(Box<SubbandSamples>, Vec<u8>)> {
+  let mut reader = Cursor::new(payload);
+  let mut bit_reader = bitreader::BitReader::new(&mut reader);
+  let table = select_allocation_table(frame_header);
+  let num_channels = frame_header.num_channels();
+  let bound = if frame_header.intensity_bound() < table.sblimit { frame_header.intensity_bound() } else { table.sblimit };
+
+  let allocations = decode_bit_allocations(&mut bit_reader, table, num_channels, bound)?;
+  let scfsi = decode_scale_factor_selection(&mut bit_reader, table, num_channels, &allocations)?;
+  let scale_factors = decode_scale_factors(&mut bit_reader, table, num_channels, &allocations, &scfsi)?;
+
+  let samples = decode_samples(&mut bit_reader, table, num_channels, bound, &allocations, &scale_factors)?;
+
+  match layer1::read_ancillary_data(&mut bit_reader, payload.len()) {
+    Ok(ancillary) => Ok((samples, ancillary)),
+    Err(e) => Err(e)
+  }
+}
+
+/// The number of bits after the CRC check word that the check covers, which
+/// are those of the bit allocations and scale factor selection information
+/// at the start of `data`.
+pub fn protected_bits(frame_header: &header::Header, data: &[u8]) -> error::Result<usize> {
+  let mut reader = Cursor::new(data);
+  let mut bit_reader = bitreader::BitReader::new(&mut reader);
+  let table = select_allocation_table(frame_header);
+  let num_channels = frame_header.num_channels();
+  let bound = if frame_header.intensity_bound() < table.sblimit { frame_header.intensity_bound() } else { table.sblimit };
+
+  let allocations = decode_bit_allocations(&mut bit_reader, table, num_channels, bound)?;
+
+  match decode_scale_factor_selection(&mut bit_reader, table, num_channels, &allocations) {
+    Ok(_) => {}, Err(e) => return Err(e)
+  };
+
+  Ok(bit_reader.position() as usize)
+}
+
+fn decode_bit_allocations(bit_reader: &mut bitreader::BitReader, table: &AllocationTable, num_channels: usize, bound: usize) -> error::Result<Box<[[u32; 32]; 2]>> {
+  let mut allocations = Box::new([[0u32; 32]; 2]);
+
+  for subband in 0..table.sblimit {
+    let nbal = SUBBAND_ALLOCATIONS[table.subbands[subband]].nbal;
+
+    // Above the bound both channels share a single allocation.
+    if subband >= bound {
+      let allocation = bit_reader.read_bits(nbal)?;
+
+      allocations[0][subband] = allocation;
+      allocations[1][subband] = allocation;
+      continue;
+    }
+
+    for channel in 0..num_channels {
+      allocations[channel][subband] = bit_reader.read_bits(nbal)?;
+    }
+  }
+
+  Ok(allocations)
+}
+
+fn decode_scale_factor_selection(bit_reader: &mut bitreader::BitReader, table: &AllocationTable, num_channels: usize, allocations: &[[u32; 32]; 2]) -> error::Result<Box<[[u32; 32]; 2]>> {
+  let mut scfsi = Box::new([[0u32; 32]; 2]);
+
+  for subband in 0..table.sblimit {
+    for channel in 0..num_channels {
+      if allocations[channel][subband] != 0 {
+        scfsi[channel][subband] = bit_reader.read_bits(2)?;
+      }
+    }
+  }
+
+  Ok(scfsi)
+}
+
+/// Reads the scale factors of the three parts of each subband. The scale
+/// factor selection information says which of them are transmitted and which
+/// are shared with the previous part.
+fn decode_scale_factors(bit_reader: &mut bitreader::BitReader, table: &AllocationTable, num_channels: usize, allocations: &[[u32; 32]; 2], scfsi: &[[u32; 32]; 2]) -> error::Result<Box<[[[u32; 32]; 3]; 2]>> {
+  let mut scale_factors = Box::new([[[0u32; 32]; 3]; 2]);
+
+  for subband in 0..table.sblimit {
+    for channel in 0..num_channels {
+      if allocations[channel][subband] == 0 {
+        continue;
+      }
+
+      let transmitted = match scfsi[channel][subband] {
+        0 => [true, true, true],
+        1 => [true, false, true],
+        2 => [true, false, false],
+        _ => [true, true, false]
+      };
+
+      for part in 0..3 {
+        scale_factors[channel][part][subband] = if transmitted[part] {
+          bit_reader.read_bits(6)?
+        } else {
+          scale_factors[channel][part - 1][subband]
+        };
+      }
+    }
+  }
+
+  Ok(scale_factors)
+}
+
+fn decode_samples(bit_reader: &mut bitreader::BitReader, table: &AllocationTable, num_channels: usize, bound: usize, allocations: &[[u32; 32]; 2], scale_factors: &[[[u32; 32]; 3]; 2]) -> error::Result<Box<[[[f64; 32]; 36]; 2]>> {
+  let mut samples = Box::new([[[0f64; 32]; 36]; 2]);
+
+  // Each of the 12 granules holds three consecutive samples of every subband,
+  // and every four granules make up one part with its own scale factor.
+  for granule in 0..12 {
+    let part = granule / 4;
+
+    for subband in 0..table.sblimit {
+      let subband_allocation = &SUBBAND_ALLOCATIONS[table.subbands[subband]];
+      let coded_channels = if subband >= bound { 1 } else { num_channels };
+
+      for (channel, channel_allocations) in allocations.iter().enumerate().take(coded_channels) {
+        let allocation = channel_allocations[subband] as usize;
+
+        if allocation == 0 {
+          continue;
+        }
+
+        let class = &QUANTIZATION_CLASSES[subband_allocation.classes[allocation]];
+        let triplet = decode_triplet(bit_reader, class)?;
+
+        // Samples above the bound are shared, each channel applying its own
+        // scale factor.
+        let channels = if subband >= bound { 0..num_channels } else { channel..channel + 1 };
+
+        for output_channel in channels {
+          let scale_factor = SCALE_FACTORS_TABLE[scale_factors[output_channel][part][subband] as usize];
+
+          for i in 0..3 {
+            samples[output_channel][granule * 3 + i][subband] = triplet[i] * scale_factor;
+          }
+        }
+      }
+    }
+  }
+
+  Ok(samples)
+}
+
+fn decode_triplet(bit_reader: &mut bitreader::BitReader, class: &QuantizationClass) -> error::Result<[f64; 3]> {
+  let mut codes = [0u32; 3];
+
+  let bits = if class.grouping {
+    let mut codeword = bit_reader.read_bits(class.bits)?;
+
+    for code in codes.iter_mut() {
+      *code = codeword % class.levels;
+      codeword /= class.levels;
+    }
+
+    // The ungrouped samples are as wide as the smallest code holding all the
+    // levels.
+    32 - class.levels.leading_zeros()
+  } else {
+    for code in codes.iter_mut() {
+      *code = bit_reader.read_bits(class.bits)?;
+    }
+
+    class.bits
+  };
+
+  let mut triplet = [0f64; 3];
+
+  for i in 0..3 {
+    // As in Layer I, inverting the most significant bit gives a two's
+    // complement fraction (2.4.3.3.4).
+    let fraction = (codes[i] as f64) / ((1u64 << (bits - 1)) as f64) - 1.0;
+
+    triplet[i] = class.c * (fraction + class.d);
+  }
+
+  Ok(triplet)
+}
+
+#[cfg(test)]
+use std::io;
+
+#[test]
+fn test_grouped_and_ungrouped_triplets() {
+  let buf = Cursor::new(vec![0xA8, 0x78]);
+  let mut br = io::BufReader::new(buf);
+  let mut r = bitreader::BitReader::new(&mut br);
+
+  let grouped = decode_triplet(&mut r, &QUANTIZATION_CLASSES[0]).unwrap();
+  assert!((grouped[0] + 2.0 / 3.0).abs() < 1e-9);
+  assert!((grouped[1] - 0.0).abs() < 1e-9);
+  assert!((grouped[2] - 2.0 / 3.0).abs() < 1e-9);
+
+  let ungrouped = decode_triplet(&mut r, &QUANTIZATION_CLASSES[2]).unwrap();
+  assert!((ungrouped[0] + 6.0 / 7.0).abs() < 1e-9);
+  assert!((ungrouped[1] - 0.0).abs() < 1e-9);
+  assert!((ungrouped[2] - 6.0 / 7.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_allocation_table_selection() {
+  let stereo_192 = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFDA000)).unwrap();
+  assert_eq!(select_allocation_table(&stereo_192).sblimit, 30);
+
+  let stereo_64_32khz = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFD4800)).unwrap();
+  assert_eq!(select_allocation_table(&stereo_64_32khz).sblimit, 12);
+
+  let mono_64_48khz = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFD54C0)).unwrap();
+  assert_eq!(select_allocation_table(&mono_64_48khz).sblimit, 27);
+}
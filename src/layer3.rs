@@ -0,0 +1,1224 @@
+use std::io::Cursor;
+use std::f64::consts::PI;
+
+use bitreader;
+use error;
+use header;
+use huffman;
+use imdct;
+
+/// Number of bits used for the scale factors of bands 0-10 and 11-20 of long
+/// blocks (slen1 and slen2) for each value of scalefac_compress.
+static SCALE_FACTOR_LENGTHS: [[u32; 2]; 16] = [
+  [0, 0], [0, 1], [0, 2], [0, 3], [3, 0], [1, 1], [1, 2], [1, 3],
+  [2, 1], [2, 2], [2, 3], [3, 1], [3, 2], [3, 3], [4, 2], [4, 3]
+];
+
+/// The first long block scale factor band of each of the four groups that
+/// scfsi can share between granules.
+static SCALE_FACTOR_SHARE_BANDS: [usize; 5] = [0, 6, 11, 16, 21];
+
+/// Number of scale factors in each of the four partitions of LSF streams
+/// (table B.3 of ISO 13818-3), for long, short and mixed blocks. The first
+/// three rows are selected by scalefac_compress, the last three by the
+/// scalefac_compress of the intensity stereo right channel.
+static LSF_PARTITION_LENGTHS: [[[usize; 4]; 3]; 6] = [
+  [[6, 5, 5, 5], [9, 9, 9, 9], [6, 9, 9, 9]],
+  [[6, 5, 7, 3], [9, 9, 12, 6], [6, 9, 12, 6]],
+  [[11, 10, 0, 0], [18, 18, 0, 0], [15, 18, 0, 0]],
+  [[7, 7, 7, 0], [12, 12, 12, 0], [6, 15, 12, 0]],
+  [[6, 6, 6, 3], [12, 9, 9, 6], [6, 12, 9, 6]],
+  [[8, 8, 5, 0], [15, 12, 9, 0], [6, 18, 9, 0]]
+];
+
+/// Scale factor band boundaries in spectral values, for long blocks and for
+/// each window of short blocks. Mixed blocks start with the long block bands
+/// below `mixed_long` and go on with the short block bands from 3. At 8 kHz
+/// short block band 3 starts at 72, so the 6 long block bands coded for mixed
+/// blocks cover that far, past the 36 values of the two long block subbands.
+struct ScaleFactorBands {
+  long: [usize; 23],
+  short: [usize; 14],
+  mixed_long: usize
+}
+
+/// The bands at 44.1, 48 and 32 kHz from table 3-B.8 of ISO 11172-3, at 22.05,
+/// 24 and 16 kHz from table B.2 of ISO 13818-3, and at the MPEG 2.5 rates of
+/// 11.025, 12 and 8 kHz.
+static SCALE_FACTOR_BANDS: [ScaleFactorBands; 9] = [
+  ScaleFactorBands {
+    long: [0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 52, 62, 74, 90, 110, 134, 162, 196, 238, 288, 342, 418, 576],
+    short: [0, 4, 8, 12, 16, 22, 30, 40, 52, 66, 84, 106, 136, 192],
+    mixed_long: 8
+  },
+  ScaleFactorBands {
+    long: [0, 4, 8, 12, 16, 20, 24, 30, 36, 42, 50, 60, 72, 88, 106, 128, 156, 190, 230, 276, 330, 384, 576],
+    short: [0, 4, 8, 12, 16, 22, 28, 38, 50, 64, 80, 100, 126, 192],
+    mixed_long: 8
+  },
+  ScaleFactorBands {
+    long: [0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 54, 66, 82, 102, 126, 156, 194, 240, 296, 364, 448, 550, 576],
+    short: [0, 4, 8, 12, 16, 22, 30, 42, 58, 78, 104, 138, 180, 192],
+    mixed_long: 8
+  },
+  ScaleFactorBands {
+    long: [0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464, 522, 576],
+    short: [0, 4, 8, 12, 18, 24, 32, 42, 56, 74, 100, 132, 174, 192],
+    mixed_long: 6
+  },
+  ScaleFactorBands {
+    long: [0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 114, 136, 162, 194, 232, 278, 332, 394, 464, 540, 576],
+    short: [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 136, 180, 192],
+    mixed_long: 6
+  },
+  ScaleFactorBands {
+    long: [0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464, 522, 576],
+    short: [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    mixed_long: 6
+  },
+  ScaleFactorBands {
+    long: [0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464, 522, 576],
+    short: [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    mixed_long: 6
+  },
+  ScaleFactorBands {
+    long: [0, 6, 12, 18, 24, 30, 36, 44, 54, 66, 80, 96, 116, 140, 168, 200, 238, 284, 336, 396, 464, 522, 576],
+    short: [0, 4, 8, 12, 18, 26, 36, 48, 62, 80, 104, 134, 174, 192],
+    mixed_long: 6
+  },
+  ScaleFactorBands {
+    long: [0, 12, 24, 36, 48, 60, 72, 88, 108, 132, 160, 192, 232, 280, 336, 400, 476, 566, 568, 570, 572, 574, 576],
+    short: [0, 8, 16, 24, 36, 52, 72, 96, 124, 160, 162, 164, 166, 192],
+    mixed_long: 6
+  }
+];
+
+fn scale_factor_bands(samplerate: u32) -> &'static ScaleFactorBands {
+  match samplerate {
+    48000 => &SCALE_FACTOR_BANDS[1],
+    32000 => &SCALE_FACTOR_BANDS[2],
+    22050 => &SCALE_FACTOR_BANDS[3],
+    24000 => &SCALE_FACTOR_BANDS[4],
+    16000 => &SCALE_FACTOR_BANDS[5],
+    11025 => &SCALE_FACTOR_BANDS[6],
+    12000 => &SCALE_FACTOR_BANDS[7],
+    8000 => &SCALE_FACTOR_BANDS[8],
+    _ => &SCALE_FACTOR_BANDS[0]
+  }
+}
+
+/// Amplification of the long block scale factors with preflag (table
+/// 3-B.6).
+static PRETAB: [u32; 22] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 3, 2, 0];
+
+/// The coefficients c[i] of the alias reduction butterflies (table 3-B.9).
+static ALIAS_COEFFICIENTS: [f64; 8] = [-0.6, -0.535, -0.33, -0.185, -0.095, -0.041, -0.0142, -0.0037];
+
+/// The largest value main_data_begin can point back.
+pub const MAX_MAIN_DATA_BEGIN: usize = 511;
+
+#[derive(Debug,Clone,Copy)]
+pub struct GranuleSideInfo {
+  pub part2_3_length: u32,
+  pub big_values: u32,
+  pub global_gain: u32,
+  pub scalefac_compress: u32,
+  pub window_switching: bool,
+  pub block_type: u32,
+  pub mixed_block: bool,
+  pub table_select: [u32; 3],
+  pub subblock_gain: [u32; 3],
+  pub region0_count: u32,
+  pub region1_count: u32,
+  pub preflag: bool,
+  pub scalefac_scale: bool,
+  pub count1table_select: bool
+}
+
+impl GranuleSideInfo {
+  fn new() -> GranuleSideInfo {
+    GranuleSideInfo {
+      part2_3_length: 0, big_values: 0, global_gain: 0, scalefac_compress: 0,
+      window_switching: false, block_type: 0, mixed_block: false,
+      table_select: [0; 3], subblock_gain: [0; 3], region0_count: 0, region1_count: 0,
+      preflag: false, scalefac_scale: false, count1table_select: false
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct SideInfo {
+  pub main_data_begin: u32,
+  pub scfsi: [[bool; 4]; 2],
+  pub granules: [[GranuleSideInfo; 2]; 2]
+}
+
+/// The main data of one channel in one granule.
+pub struct GranuleData {
+  pub scalefac_l: [u32; 22],
+  pub scalefac_s: [[u32; 3]; 13],
+  pub samples: [i32; 576],
+  /// The intensity positions from which a band of the right channel is not
+  /// intensity coded. That is 7 in MPEG-1, and the largest value the scale
+  /// factor of the band can take in LSF streams.
+  pub illegal_position_l: [u32; 22],
+  pub illegal_position_s: [u32; 13]
+}
+
+impl GranuleData {
+  fn new() -> GranuleData {
+    GranuleData {
+      scalefac_l: [0; 22],
+      scalefac_s: [[0; 3]; 13],
+      samples: [0; 576],
+      illegal_position_l: [7; 22],
+      illegal_position_s: [7; 13]
+    }
+  }
+}
+
+/// The decoded contents of a Layer III frame, indexed by granule and channel.
+/// LSF frames only have the first granule.
+pub struct Layer3Frame {
+  pub side_info: SideInfo,
+  pub granules: [[GranuleData; 2]; 2]
+}
+
+/// Layer III decoding state, which holds on to the main data of previous
+/// frames for the bit reservoir.
+pub struct Layer3Decoder {
+  reservoir: Vec<u8>,
+  huffman: huffman::HuffmanDecoder,
+  imdct: imdct::Imdct,
+  overlap: Box<[[[f64; 18]; 32]; 2]>
+}
+
+impl Layer3Decoder {
+  pub fn new() -> Layer3Decoder {
+    Layer3Decoder {
+      reservoir: Vec::new(),
+      huffman: huffman::HuffmanDecoder::new(),
+      imdct: imdct::Imdct::new(),
+      overlap: Box::new([[[0f64; 18]; 32]; 2])
+    }
+  }
+
+  /// Forgets the main data and overlap of previous frames, as when the
+  /// stream is entered somewhere else.
+  pub fn reset(&mut self) {
+    self.reservoir.clear();
+    *self.overlap = [[[0f64; 18]; 32]; 2];
+  }
+
+  /// Decodes the payload of a frame into 18 samples of each of the 32
+  /// subbands per granule, which is 36 like Layer II, or 18 for LSF frames.
+  pub fn decode_layer3(&mut self, payload: &[u8], frame_header: &header::Header) -> error::Result<Box<[[[f64; 32]; 36]; 2]>> {
+    let frame = self.read_frame(payload, frame_header)?;
+    let num_channels = frame_header.num_channels();
+    let bands = scale_factor_bands(frame_header.samplerate.unwrap_or(44100));
+    let mut samples = Box::new([[[0f64; 32]; 36]; 2]);
+
+    for granule in 0..num_granules(frame_header) {
+      let side_info = &frame.side_info.granules[granule];
+      let data = &frame.granules[granule];
+      let mut xr = Box::new([[0f64; 576]; 2]);
+
+      for channel in 0..num_channels {
+        requantize(&side_info[channel], &data[channel], bands, &mut xr[channel]);
+      }
+
+      if frame_header.channel_mode == 1 {
+        process_stereo(frame_header, side_info, data, bands, &mut xr);
+      }
+
+      for channel in 0..num_channels {
+        if is_short_block(&side_info[channel]) {
+          reorder(&side_info[channel], bands, &mut xr[channel]);
+        }
+
+        reduce_aliasing(&side_info[channel], &mut xr[channel]);
+
+        self.hybrid_synthesis(channel, &side_info[channel], &xr[channel], &mut samples[channel][granule * 18..(granule + 1) * 18]);
+      }
+    }
+
+    Ok(samples)
+  }
+
+  /// Runs the IMDCT of every subband, overlapping the first half of its
+  /// output with the second half kept from the previous granule.
+  fn hybrid_synthesis(&mut self, channel: usize, side_info: &GranuleSideInfo, xr: &[f64; 576], output: &mut [[f64; 32]]) {
+    let mut block = [0f64; 36];
+
+    for subband in 0..32 {
+      // The two lowest subbands of mixed blocks use the normal long window.
+      let block_type = if !side_info.window_switching || (side_info.mixed_block && subband < 2) {
+        0
+      } else {
+        side_info.block_type
+      };
+
+      self.imdct.transform(&xr[subband * 18..(subband + 1) * 18], block_type, &mut block);
+
+      for i in 0..18 {
+        let mut sample = block[i] + self.overlap[channel][subband][i];
+
+        self.overlap[channel][subband][i] = block[18 + i];
+
+        // Every odd sample of every odd subband is inverted to compensate
+        // for the frequency inversion of the polyphase filterbank.
+        if subband % 2 == 1 && i % 2 == 1 {
+          sample = -sample;
+        }
+
+        output[i][subband] = sample;
+      }
+    }
+  }
+
+  /// Passes over the payload of a frame without decoding it, keeping its
+  /// main data for the frames that follow.
+  pub fn skip_frame(&mut self, payload: &[u8], frame_header: &header::Header) -> error::Result<()> {
+    let side_info_length = side_info_length(frame_header);
+
+    if payload.len() < side_info_length {
+      return Err(error::Error::FrameOverrun(None));
+    }
+
+    self.append_main_data(&payload[side_info_length..]);
+
+    Ok(())
+  }
+
+  /// Reads the side information and main data of the payload of a frame.
+  pub fn read_frame(&mut self, payload: &[u8], frame_header: &header::Header) -> error::Result<Layer3Frame> {
+    let side_info_length = side_info_length(frame_header);
+
+    if payload.len() < side_info_length {
+      return Err(error::Error::FrameOverrun(None));
+    }
+
+    let side_info = {
+      let mut reader = Cursor::new(&payload[..side_info_length]);
+      let mut bit_reader = bitreader::BitReader::new(&mut reader);
+
+      decode_side_info(&mut bit_reader, frame_header)?
+    };
+
+    let main_data_begin = side_info.main_data_begin as usize;
+    let mut frame = Layer3Frame {
+      side_info,
+      granules: [[GranuleData::new(), GranuleData::new()], [GranuleData::new(), GranuleData::new()]]
+    };
+
+    // The main data of this frame may start in the main data of previous
+    // frames. If those were never seen (at the start of the stream) the frame
+    // is left silent.
+    let available = self.reservoir.len();
+    self.reservoir.extend(payload[side_info_length..].iter().cloned());
+
+    let result = if main_data_begin <= available {
+      decode_main_data(&self.reservoir[available - main_data_begin..], &mut frame, frame_header, &self.huffman)
+    } else {
+      Ok(())
+    };
+
+    self.trim_reservoir();
+
+    match result {
+      Ok(_) => Ok(frame),
+      Err(e) => Err(e)
+    }
+  }
+
+  fn append_main_data(&mut self, main_data: &[u8]) {
+    self.reservoir.extend(main_data.iter().cloned());
+    self.trim_reservoir();
+  }
+
+  /// Drops what later frames can no longer refer back to from the reservoir.
+  fn trim_reservoir(&mut self) {
+    if self.reservoir.len() > MAX_MAIN_DATA_BEGIN {
+      let excess = self.reservoir.len() - MAX_MAIN_DATA_BEGIN;
+      self.reservoir.drain(..excess);
+    }
+  }
+}
+
+/// MPEG-1 frames hold two granules of 576 samples, and the LSF frames of
+/// MPEG-2 and MPEG-2.5 only one.
+fn num_granules(frame_header: &header::Header) -> usize {
+  if frame_header.version == header::MpegVersion::MPEG1_0 { 2 } else { 1 }
+}
+
+/// The number of bytes of side information following the header.
+pub fn side_info_length(frame_header: &header::Header) -> usize {
+  match (num_granules(frame_header), frame_header.num_channels()) {
+    (2, 1) => 17, (2, _) => 32, (_, 1) => 9, _ => 17
+  }
+}
+
+/// The number of bits after the CRC check word that the check covers, which
+/// are those of the side information.
+pub fn protected_bits(frame_header: &header::Header) -> usize {
+  side_info_length(frame_header) * 8
+}
+
+/// Whether the right channel of the frame holds intensity positions.
+fn is_intensity_stereo(frame_header: &header::Header) -> bool {
+  frame_header.channel_mode == 1 && frame_header.channel_mode_extension & 0x1 != 0
+}
+
+fn decode_side_info(bit_reader: &mut bitreader::BitReader, frame_header: &header::Header) -> error::Result<SideInfo> {
+  let num_channels = frame_header.num_channels();
+  let lsf = num_granules(frame_header) == 1;
+
+  // LSF side information has a shorter main_data_begin and no scfsi.
+  let main_data_begin = bit_reader.read_bits(if lsf { 8 } else { 9 })?;
+  // The private bits are of no use to the decoder.
+  bit_reader.skip_bits(match (lsf, num_channels) { (false, 1) => 5, (false, _) => 3, (true, 1) => 1, _ => 2 })?;
+
+  let mut scfsi = [[false; 4]; 2];
+
+  for channel_scfsi in scfsi.iter_mut().take(if lsf { 0 } else { num_channels }) {
+    for group in channel_scfsi.iter_mut() {
+      *group = bit_reader.read_bits(1)? == 1;
+    }
+  }
+
+  let mut granules = [[GranuleSideInfo::new(); 2]; 2];
+
+  for granule in granules.iter_mut().take(num_granules(frame_header)) {
+    for (channel, channel_side_info) in granule.iter_mut().enumerate().take(num_channels) {
+      let mut side_info = decode_granule_side_info(bit_reader, lsf)?;
+
+      // LSF streams have no preflag bit. It is implied by the largest values
+      // of scalefac_compress, except for intensity positions.
+      if lsf && !(channel == 1 && is_intensity_stereo(frame_header)) {
+        side_info.preflag = side_info.scalefac_compress >= 500;
+      }
+
+      *channel_side_info = side_info;
+    }
+  }
+
+  Ok(SideInfo {
+    main_data_begin,
+    scfsi,
+    granules
+  })
+}
+
+fn decode_granule_side_info(bit_reader: &mut bitreader::BitReader, lsf: bool) -> error::Result<GranuleSideInfo> {
+  // Every field is read in bitstream order, as listed in 2.4.1.7.
+  let mut fields = [0u32; 4];
+
+  for (i, &bits) in [12, 9, 8, if lsf { 9 } else { 4 }].iter().enumerate() {
+    fields[i] = bit_reader.read_bits(bits)?;
+  }
+
+  let window_switching = match bit_reader.read_bits(1) { Ok(v) => v == 1, Err(e) => return Err(e) };
+
+  let mut block_type = 0;
+  let mut mixed_block = false;
+  let mut table_select = [0u32; 3];
+  let mut subblock_gain = [0u32; 3];
+  let region0_count;
+  let region1_count;
+
+  if window_switching {
+    block_type = bit_reader.read_bits(2)?;
+    mixed_block = match bit_reader.read_bits(1) { Ok(v) => v == 1, Err(e) => return Err(e) };
+
+    for table in table_select.iter_mut().take(2) {
+      *table = bit_reader.read_bits(5)?;
+    }
+
+    for gain in subblock_gain.iter_mut() {
+      *gain = bit_reader.read_bits(3)?;
+    }
+
+    // The region boundaries are implicit with window switching, and region 2
+    // is empty.
+    region0_count = if block_type == 2 && !mixed_block { 8 } else { 7 };
+    region1_count = 36;
+  } else {
+    for table in table_select.iter_mut() {
+      *table = bit_reader.read_bits(5)?;
+    }
+
+    region0_count = bit_reader.read_bits(4)?;
+    region1_count = bit_reader.read_bits(3)?;
+  }
+
+  let mut flags = [false; 3];
+
+  for i in if lsf { 1..3 } else { 0..3 } {
+    flags[i] = match bit_reader.read_bits(1) { Ok(v) => v == 1, Err(e) => return Err(e) };
+  }
+
+  Ok(GranuleSideInfo {
+    part2_3_length: fields[0],
+    big_values: fields[1],
+    global_gain: fields[2],
+    scalefac_compress: fields[3],
+    window_switching,
+    block_type,
+    mixed_block,
+    table_select,
+    subblock_gain,
+    region0_count,
+    region1_count,
+    preflag: flags[0],
+    scalefac_scale: flags[1],
+    count1table_select: flags[2]
+  })
+}
+
+fn decode_main_data(main_data: &[u8], frame: &mut Layer3Frame, frame_header: &header::Header, huffman: &huffman::HuffmanDecoder) -> error::Result<()> {
+  let num_channels = frame_header.num_channels();
+  let bands = scale_factor_bands(frame_header.samplerate.unwrap_or(44100));
+  let mut offset = 0u64;
+
+  for granule in 0..num_granules(frame_header) {
+    for channel in 0..num_channels {
+      let side_info = frame.side_info.granules[granule][channel];
+      let end = offset + side_info.part2_3_length as u64;
+
+      if end > main_data.len() as u64 * 8 {
+        return Err(error::Error::FrameOverrun(None));
+      }
+
+      // Every granule gets its own reader, since the count1 values may
+      // overshoot part2_3_length and have to be backed out of.
+      let mut cursor = Cursor::new(&main_data[(offset / 8) as usize..]);
+      let mut bit_reader = bitreader::BitReader::new(&mut cursor);
+
+      match bit_reader.skip_bits(offset % 8) {
+        Ok(_) => {}, Err(e) => return Err(e)
+      };
+
+      let end = end - offset + offset % 8;
+
+      // With scfsi, the second granule reuses the long block scale factors
+      // of the first one for some of the bands.
+      let previous = if granule == 1 { Some(frame.granules[0][channel].scalefac_l) } else { None };
+      let data = &mut frame.granules[granule][channel];
+
+      let result = if num_granules(frame_header) == 2 {
+        decode_scale_factors(&mut bit_reader, &side_info, &frame.side_info.scfsi[channel], previous.as_ref(), data)
+      } else {
+        decode_lsf_scale_factors(&mut bit_reader, &side_info, channel == 1 && is_intensity_stereo(frame_header), data)
+      };
+
+      match result {
+        Ok(_) => {}, Err(e) => return Err(e)
+      };
+
+      match decode_spectrum(&mut bit_reader, huffman, &side_info, bands, end, &mut data.samples) {
+        Ok(_) => {}, Err(e) => return Err(e)
+      };
+
+      offset += side_info.part2_3_length as u64;
+    }
+  }
+
+  Ok(())
+}
+
+/// The first spectral values of regions 1 and 2 of the big values.
+fn region_boundaries(side_info: &GranuleSideInfo, bands: &ScaleFactorBands) -> (usize, usize) {
+  if side_info.window_switching {
+    // Region 0 holds 3 short block bands (3 windows each) or 8 long block
+    // bands, and region 1 all the rest. For mixed blocks region 0 ends with
+    // the two long block subbands, at 36 for every sample rate.
+    let region1_start = if side_info.block_type != 2 {
+      bands.long[8]
+    } else if !side_info.mixed_block {
+      bands.short[3] * 3
+    } else {
+      36
+    };
+
+    return (region1_start, 576);
+  }
+
+  let region1_band = side_info.region0_count as usize + 1;
+  let region2_band = region1_band + side_info.region1_count as usize + 1;
+
+  (bands.long[region1_band], bands.long[if region2_band < 22 { region2_band } else { 22 }])
+}
+
+/// Decodes the Huffman coded spectral values of a granule, which end `end`
+/// bits into `bit_reader`.
+fn decode_spectrum(bit_reader: &mut bitreader::BitReader, huffman: &huffman::HuffmanDecoder, side_info: &GranuleSideInfo, bands: &ScaleFactorBands, end: u64, samples: &mut [i32; 576]) -> error::Result<()> {
+  if side_info.big_values > 288 {
+    return Err(error::Error::InvalidData("big_values out of range", None));
+  }
+
+  let big_values_end = side_info.big_values as usize * 2;
+  let (region1_start, region2_start) = region_boundaries(side_info, bands);
+  let mut i = 0;
+
+  while i < big_values_end {
+    let table_select = if i < region1_start {
+      side_info.table_select[0]
+    } else if i < region2_start {
+      side_info.table_select[1]
+    } else {
+      side_info.table_select[2]
+    };
+
+    let (x, y) = huffman.decode_pair(bit_reader, table_select)?;
+
+    samples[i] = x;
+    samples[i + 1] = y;
+    i += 2;
+  }
+
+  if bit_reader.position() > end {
+    return Err(error::Error::InvalidData("big values overrun part2_3_length", None));
+  }
+
+  // The count1 region lasts until part2_3_length is used up. A quadruple
+  // that crosses that point is not part of the granule.
+  while i + 4 <= 576 && bit_reader.position() < end {
+    let quad = huffman.decode_quad(bit_reader, side_info.count1table_select)?;
+
+    if bit_reader.position() > end {
+      break;
+    }
+
+    samples[i..i + 4].copy_from_slice(&quad);
+
+    i += 4;
+  }
+
+  Ok(())
+}
+
+fn decode_scale_factors(bit_reader: &mut bitreader::BitReader, side_info: &GranuleSideInfo, scfsi: &[bool; 4], previous: Option<&[u32; 22]>, data: &mut GranuleData) -> error::Result<()> {
+  let slen = SCALE_FACTOR_LENGTHS[side_info.scalefac_compress as usize];
+
+  if side_info.window_switching && side_info.block_type == 2 {
+    // Mixed blocks start with the 8 lowest long block bands, followed by
+    // the short block bands from 3 up.
+    let first_short_band = if side_info.mixed_block { 3 } else { 0 };
+
+    if side_info.mixed_block {
+      for band in 0..8 {
+        data.scalefac_l[band] = bit_reader.read_bits(slen[0])?;
+      }
+    }
+
+    for band in first_short_band..12 {
+      let bits = if band < 6 { slen[0] } else { slen[1] };
+
+      for window in 0..3 {
+        data.scalefac_s[band][window] = bit_reader.read_bits(bits)?;
+      }
+    }
+
+    return Ok(());
+  }
+
+  for group in 0..4 {
+    let bits = if group < 2 { slen[0] } else { slen[1] };
+
+    for band in SCALE_FACTOR_SHARE_BANDS[group]..SCALE_FACTOR_SHARE_BANDS[group + 1] {
+      data.scalefac_l[band] = match previous {
+        Some(previous) if scfsi[group] => previous[band],
+        _ => bit_reader.read_bits(bits)?
+      };
+    }
+  }
+
+  Ok(())
+}
+
+/// Decodes the scale factors of an LSF granule (2.4.3.2 of ISO 13818-3),
+/// which come in four partitions of bands, each with its own length. The
+/// right channel of intensity stereo frames codes its lengths differently.
+fn decode_lsf_scale_factors(bit_reader: &mut bitreader::BitReader, side_info: &GranuleSideInfo, intensity: bool, data: &mut GranuleData) -> error::Result<()> {
+  let (slen, table) = if intensity {
+    let c = side_info.scalefac_compress >> 1;
+
+    if c < 180 {
+      ([c / 36, (c % 36) / 6, c % 6, 0], 3)
+    } else if c < 244 {
+      ([((c - 180) % 64) >> 4, ((c - 180) % 16) >> 2, (c - 180) % 4, 0], 4)
+    } else {
+      ([(c - 244) / 3, (c - 244) % 3, 0, 0], 5)
+    }
+  } else {
+    let c = side_info.scalefac_compress;
+
+    if c < 400 {
+      ([(c >> 4) / 5, (c >> 4) % 5, (c % 16) >> 2, c % 4], 0)
+    } else if c < 500 {
+      ([((c - 400) >> 2) / 5, ((c - 400) >> 2) % 5, (c - 400) % 4, 0], 1)
+    } else {
+      ([(c - 500) / 3, (c - 500) % 3, 0, 0], 2)
+    }
+  };
+
+  let short = is_short_block(side_info);
+  let block = if !short { 0 } else if !side_info.mixed_block { 1 } else { 2 };
+
+  // Mixed blocks start with 6 long block bands, and short blocks take one
+  // scale factor per window.
+  let long_bands = if !short { 22 } else if side_info.mixed_block { 6 } else { 0 };
+  let first_short_band = if side_info.mixed_block { 3 } else { 0 };
+  let mut i = 0;
+
+  for partition in 0..4 {
+    // The largest value of each length is an illegal intensity position, but
+    // bands coded without any bits are at the legal position 0.
+    let illegal_position = if slen[partition] > 0 { (1 << slen[partition]) - 1 } else { 1 };
+
+    for _ in 0..LSF_PARTITION_LENGTHS[table][block][partition] {
+      let value = bit_reader.read_bits(slen[partition])?;
+
+      if i < long_bands {
+        data.scalefac_l[i] = value;
+        data.illegal_position_l[i] = illegal_position;
+      } else {
+        let band = first_short_band + (i - long_bands) / 3;
+
+        data.scalefac_s[band][(i - long_bands) % 3] = value;
+        data.illegal_position_s[band] = illegal_position;
+      }
+
+      i += 1;
+    }
+  }
+
+  Ok(())
+}
+
+fn is_short_block(side_info: &GranuleSideInfo) -> bool {
+  side_info.window_switching && side_info.block_type == 2
+}
+
+/// Requantizes the Huffman decoded values with the global gain, subblock gain
+/// and scale factors (2.4.3.4.7.1). Short block values stay in the order
+/// they were coded in, band by band and window by window.
+fn requantize(side_info: &GranuleSideInfo, data: &GranuleData, bands: &ScaleFactorBands, xr: &mut [f64; 576]) {
+  let gain = side_info.global_gain as f64 - 210.0;
+  let multiplier = if side_info.scalefac_scale { 1.0 } else { 0.5 };
+
+  let mut long_bands = 22;
+  let mut first_short_band = 13;
+
+  if is_short_block(side_info) {
+    long_bands = if side_info.mixed_block { bands.mixed_long } else { 0 };
+    first_short_band = if side_info.mixed_block { 3 } else { 0 };
+  }
+
+  for (band, &pretab) in PRETAB.iter().enumerate().take(long_bands) {
+    let preemphasis = if side_info.preflag { pretab } else { 0 };
+    let exponent = 0.25 * gain - multiplier * (data.scalefac_l[band] + preemphasis) as f64;
+    let (start, end) = (bands.long[band], bands.long[band + 1]);
+
+    for (x, &sample) in xr[start..end].iter_mut().zip(data.samples[start..end].iter()) {
+      *x = requantize_value(sample, exponent);
+    }
+  }
+
+  for band in first_short_band..13 {
+    let width = bands.short[band + 1] - bands.short[band];
+
+    for window in 0..3 {
+      let exponent = 0.25 * (gain - 8.0 * side_info.subblock_gain[window] as f64) - multiplier * data.scalefac_s[band][window] as f64;
+      let start = bands.short[band] * 3 + window * width;
+
+      for (x, &sample) in xr[start..start + width].iter_mut().zip(data.samples[start..start + width].iter()) {
+        *x = requantize_value(sample, exponent);
+      }
+    }
+  }
+}
+
+fn requantize_value(value: i32, exponent: f64) -> f64 {
+  if value == 0 {
+    return 0.0;
+  }
+
+  let magnitude = (value.abs() as f64).powf(4.0 / 3.0) * 2f64.powf(exponent);
+
+  if value < 0 { -magnitude } else { magnitude }
+}
+
+/// Applies mid/side and intensity stereo to the requantized values of a
+/// joint stereo granule (2.4.3.4.9).
+fn process_stereo(frame_header: &header::Header, side_info: &[GranuleSideInfo; 2], data: &[GranuleData; 2], bands: &ScaleFactorBands, xr: &mut [[f64; 576]; 2]) {
+  let mid_side = frame_header.channel_mode_extension & 0x2 != 0;
+
+  // The intensity positions of LSF streams are scaled as selected by the
+  // lowest bit of scalefac_compress of the right channel.
+  let intensity_scale = if num_granules(frame_header) == 1 { Some(side_info[1].scalefac_compress & 1) } else { None };
+
+  // Intensity stereo covers the bands above the highest non-zero value of
+  // the right channel, and the bound is where mid/side stereo ends.
+  let bound = if !is_intensity_stereo(frame_header) {
+    576
+  } else if is_short_block(&side_info[1]) {
+    process_intensity_short(&side_info[1], &data[1], bands, intensity_scale, mid_side, xr)
+  } else {
+    process_intensity_long(&data[1], bands, intensity_scale, mid_side, xr)
+  };
+
+  if mid_side {
+    process_mid_side(xr, 0, bound);
+  }
+}
+
+fn process_mid_side(xr: &mut [[f64; 576]; 2], start: usize, end: usize) {
+  let (first, second) = xr.split_at_mut(1);
+
+  for (x0, x1) in first[0][start..end].iter_mut().zip(second[0][start..end].iter_mut()) {
+    let mid = *x0;
+    let side = *x1;
+
+    *x0 = (mid + side) / 2f64.sqrt();
+    *x1 = (mid - side) / 2f64.sqrt();
+  }
+}
+
+/// Spreads the values coded in the left channel over both channels, as given
+/// by the intensity position. Bands with an illegal intensity position are
+/// left to mid/side stereo.
+fn process_intensity_band(xr: &mut [[f64; 576]; 2], start: usize, end: usize, position: u32, illegal_position: u32, intensity_scale: Option<u32>, mid_side: bool) {
+  if position >= illegal_position {
+    if mid_side {
+      process_mid_side(xr, start, end);
+    }
+
+    return;
+  }
+
+  let (left, right) = match intensity_scale {
+    // In LSF streams every step of the position attenuates one of the
+    // channels by 1.5 or 3 dB (2.4.3.2 of ISO 13818-3).
+    Some(scale) => {
+      let gain = 2f64.powf(-((((position + 1) >> 1) << scale) as f64) / 4.0);
+
+      if position % 2 == 1 { (gain, 1.0) } else { (1.0, gain) }
+    },
+    None if position == 6 => (1.0, 0.0),
+    None => {
+      let ratio = (position as f64 * PI / 12.0).tan();
+
+      (ratio / (1.0 + ratio), 1.0 / (1.0 + ratio))
+    }
+  };
+
+  let (first, second) = xr.split_at_mut(1);
+
+  for (x0, x1) in first[0][start..end].iter_mut().zip(second[0][start..end].iter_mut()) {
+    let value = *x0;
+
+    *x0 = value * left;
+    *x1 = value * right;
+  }
+}
+
+fn is_zero(samples: &[i32]) -> bool {
+  samples.iter().all(|&s| s == 0)
+}
+
+fn process_intensity_long(data: &GranuleData, bands: &ScaleFactorBands, intensity_scale: Option<u32>, mid_side: bool, xr: &mut [[f64; 576]; 2]) -> usize {
+  let mut bound = 576;
+
+  for band in (0..22).rev() {
+    let (start, end) = (bands.long[band], bands.long[band + 1]);
+
+    if !is_zero(&data.samples[start..end]) {
+      break;
+    }
+
+    // The last band has no scale factor of its own and uses the one below.
+    let coded_band = if band < 21 { band } else { 20 };
+
+    process_intensity_band(xr, start, end, data.scalefac_l[coded_band], data.illegal_position_l[coded_band], intensity_scale, mid_side);
+    bound = start;
+  }
+
+  bound
+}
+
+fn process_intensity_short(side_info: &GranuleSideInfo, data: &GranuleData, bands: &ScaleFactorBands, intensity_scale: Option<u32>, mid_side: bool, xr: &mut [[f64; 576]; 2]) -> usize {
+  let first_short_band = if side_info.mixed_block { 3 } else { 0 };
+  let mut zero = [true; 3];
+  let mut bound = 576;
+
+  // Each window has its own highest non-zero band. Below it, bands are left
+  // to mid/side stereo.
+  for band in (first_short_band..13).rev() {
+    let width = bands.short[band + 1] - bands.short[band];
+
+    for (window, window_zero) in zero.iter_mut().enumerate() {
+      let start = bands.short[band] * 3 + window * width;
+
+      *window_zero = *window_zero && is_zero(&data.samples[start..start + width]);
+
+      if *window_zero {
+        let coded_band = if band < 12 { band } else { 11 };
+
+        process_intensity_band(xr, start, start + width, data.scalefac_s[coded_band][window], data.illegal_position_s[coded_band], intensity_scale, mid_side);
+      } else if mid_side {
+        process_mid_side(xr, start, start + width);
+      }
+    }
+
+    bound = bands.short[band] * 3;
+
+    if !zero[0] && !zero[1] && !zero[2] {
+      return bound;
+    }
+  }
+
+  // The long block bands of mixed blocks only take intensity stereo when all
+  // three windows are zero down to the first short block band. Otherwise the
+  // bound lies in the short block part (2.4.3.4.9.3).
+  if side_info.mixed_block && zero.iter().all(|&z| z) {
+    for band in (0..bands.mixed_long).rev() {
+      let (start, end) = (bands.long[band], bands.long[band + 1]);
+
+      if !is_zero(&data.samples[start..end]) {
+        break;
+      }
+
+      process_intensity_band(xr, start, end, data.scalefac_l[band], data.illegal_position_l[band], intensity_scale, mid_side);
+      bound = start;
+    }
+  }
+
+  bound
+}
+
+/// Reorders the values of short blocks from band by band and window by
+/// window to interleaved windows within each subband, which is the order the
+/// IMDCT takes them in.
+fn reorder(side_info: &GranuleSideInfo, bands: &ScaleFactorBands, xr: &mut [f64; 576]) {
+  let first_short_band = if side_info.mixed_block { 3 } else { 0 };
+  let mut reordered = [0f64; 576];
+
+  for band in first_short_band..13 {
+    let start = bands.short[band];
+    let width = bands.short[band + 1] - start;
+
+    for window in 0..3 {
+      for j in 0..width {
+        reordered[(start + j) * 3 + window] = xr[start * 3 + window * width + j];
+      }
+    }
+  }
+
+  let first = bands.short[first_short_band] * 3;
+
+  xr[first..].copy_from_slice(&reordered[first..]);
+}
+
+/// Alias reduction butterflies between neighbouring subbands (2.4.3.4.10.1).
+/// Short blocks have none, and mixed blocks only between their two long
+/// block subbands.
+fn reduce_aliasing(side_info: &GranuleSideInfo, xr: &mut [f64; 576]) {
+  let subbands = if !is_short_block(side_info) {
+    32
+  } else if side_info.mixed_block {
+    2
+  } else {
+    return;
+  };
+
+  for subband in 1..subbands {
+    for i in 0..8 {
+      let c = ALIAS_COEFFICIENTS[i];
+      let cs = 1.0 / (1.0 + c * c).sqrt();
+      let ca = c / (1.0 + c * c).sqrt();
+
+      let lower = xr[subband * 18 - 1 - i];
+      let upper = xr[subband * 18 + i];
+
+      xr[subband * 18 - 1 - i] = lower * cs - upper * ca;
+      xr[subband * 18 + i] = upper * cs + lower * ca;
+    }
+  }
+}
+
+#[test]
+fn test_requantize_long_block() {
+  let mut side_info = GranuleSideInfo::new();
+  side_info.global_gain = 214;
+
+  let mut data = GranuleData::new();
+  data.samples[0] = 8;
+  data.samples[1] = -1;
+  data.scalefac_l[0] = 2;
+
+  let mut xr = [0f64; 576];
+  requantize(&side_info, &data, &SCALE_FACTOR_BANDS[0], &mut xr);
+
+  // 8^(4/3) * 2^(0.25 * 4) * 2^(-0.5 * 2) = 16
+  assert!((xr[0] - 16.0).abs() < 1e-9);
+  assert!((xr[1] + 1.0).abs() < 1e-9);
+  assert_eq!(xr[4], 0.0);
+}
+
+#[test]
+fn test_lsf_mixed_block() {
+  let mut side_info = GranuleSideInfo::new();
+  side_info.window_switching = true;
+  side_info.block_type = 2;
+  side_info.mixed_block = true;
+  side_info.global_gain = 210;
+
+  // Region 1 starts after the two long block subbands at 22.05 kHz as well.
+  assert_eq!(region_boundaries(&side_info, &SCALE_FACTOR_BANDS[3]), (36, 576));
+  assert_eq!(region_boundaries(&side_info, &SCALE_FACTOR_BANDS[8]), (36, 576));
+
+  // At 8 kHz the long block bands reach up to the short block bands at 72.
+  let mut data = GranuleData::new();
+  data.samples[36] = 1;
+  data.samples[71] = 1;
+  data.samples[72] = 1;
+
+  let mut xr = [0f64; 576];
+  requantize(&side_info, &data, &SCALE_FACTOR_BANDS[8], &mut xr);
+
+  assert_eq!(&xr[35..37], &[0.0, 1.0]);
+  assert_eq!(&xr[71..73], &[1.0, 1.0]);
+}
+
+#[test]
+fn test_reorder_short_block() {
+  let mut side_info = GranuleSideInfo::new();
+  side_info.window_switching = true;
+  side_info.block_type = 2;
+
+  let mut xr = [0f64; 576];
+
+  for (i, x) in xr.iter_mut().enumerate() {
+    *x = i as f64;
+  }
+
+  reorder(&side_info, &SCALE_FACTOR_BANDS[0], &mut xr);
+
+  // The first band is 4 lines wide, so its windows start at 0, 4 and 8.
+  assert_eq!(&xr[0..6], &[0.0, 4.0, 8.0, 1.0, 5.0, 9.0]);
+  assert_eq!(&xr[12..15], &[12.0, 16.0, 20.0]);
+}
+
+#[test]
+fn test_mid_side_stereo() {
+  let side_info = [GranuleSideInfo::new(), GranuleSideInfo::new()];
+  let data = [GranuleData::new(), GranuleData::new()];
+  let mut xr = Box::new([[0f64; 576]; 2]);
+
+  xr[0][0] = 2f64.sqrt();
+  xr[1][0] = 2f64.sqrt();
+
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB9060)).unwrap();
+
+  process_stereo(&frame_header, &side_info, &data, &SCALE_FACTOR_BANDS[0], &mut xr);
+
+  assert!((xr[0][0] - 2.0).abs() < 1e-9);
+  assert!(xr[1][0].abs() < 1e-9);
+}
+
+#[test]
+fn test_intensity_stereo_mixed_block() {
+  let mut side_info = [GranuleSideInfo::new(), GranuleSideInfo::new()];
+  let mut data = [GranuleData::new(), GranuleData::new()];
+  let mut xr = Box::new([[0f64; 576]; 2]);
+
+  side_info[1].window_switching = true;
+  side_info[1].block_type = 2;
+  side_info[1].mixed_block = true;
+
+  // The first window of the right channel has a value in the highest short
+  // block band, so the bound lies in the short block part and the long block
+  // bands are left alone.
+  data[1].samples[SCALE_FACTOR_BANDS[0].short[12] * 3] = 1;
+  xr[0][0] = 1.0;
+  xr[0][575] = 1.0;
+
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB9050)).unwrap();
+
+  process_stereo(&frame_header, &side_info, &data, &SCALE_FACTOR_BANDS[0], &mut xr);
+
+  assert_eq!((xr[0][0], xr[1][0]), (1.0, 0.0));
+
+  // The windows that are zero are intensity coded, all in the right channel
+  // at position 0.
+  assert_eq!((xr[0][575], xr[1][575]), (0.0, 1.0));
+}
+
+#[test]
+fn test_side_info_mono() {
+  // main_data_begin = 5, private = 0, scfsi = 1010, then two granules with
+  // part2_3_length = 100, big_values = 20, global_gain = 210,
+  // scalefac_compress = 9, no window switching, table_select = 1, 2, 3,
+  // region0_count = 4, region1_count = 2 and preflag only.
+  let mut w = TestBitWriter::new();
+  w.write(5, 9); w.write(0, 5); w.write(0xA, 4);
+
+  for _ in 0..2 {
+    w.write(100, 12); w.write(20, 9); w.write(210, 8); w.write(9, 4); w.write(0, 1);
+    w.write(1, 5); w.write(2, 5); w.write(3, 5); w.write(4, 4); w.write(2, 3);
+    w.write(1, 1); w.write(0, 1); w.write(0, 1);
+  }
+
+  let bytes = w.bytes();
+  assert_eq!(bytes.len(), 17);
+
+  let mut cursor = Cursor::new(bytes);
+  let mut r = bitreader::BitReader::new(&mut cursor);
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB90C0)).unwrap();
+  let side_info = decode_side_info(&mut r, &frame_header).unwrap();
+
+  assert_eq!(side_info.main_data_begin, 5);
+  assert_eq!(side_info.scfsi[0], [true, false, true, false]);
+
+  let g = side_info.granules[1][0];
+  assert_eq!(g.part2_3_length, 100);
+  assert_eq!(g.big_values, 20);
+  assert_eq!(g.global_gain, 210);
+  assert_eq!(g.scalefac_compress, 9);
+  assert!(!g.window_switching);
+  assert_eq!(g.table_select, [1, 2, 3]);
+  assert_eq!(g.region0_count, 4);
+  assert_eq!(g.region1_count, 2);
+  assert!(g.preflag);
+  assert!(!g.scalefac_scale);
+}
+
+#[test]
+fn test_short_block_side_info() {
+  let mut w = TestBitWriter::new();
+  w.write(60, 12); w.write(10, 9); w.write(150, 8); w.write(3, 4); w.write(1, 1);
+  w.write(2, 2); w.write(0, 1); w.write(5, 5); w.write(6, 5);
+  w.write(1, 3); w.write(2, 3); w.write(3, 3);
+  w.write(0, 1); w.write(1, 1); w.write(1, 1);
+
+  let mut cursor = Cursor::new(w.bytes());
+  let mut r = bitreader::BitReader::new(&mut cursor);
+  let g = decode_granule_side_info(&mut r, false).unwrap();
+
+  assert!(g.window_switching);
+  assert_eq!(g.block_type, 2);
+  assert!(!g.mixed_block);
+  assert_eq!(g.table_select, [5, 6, 0]);
+  assert_eq!(g.subblock_gain, [1, 2, 3]);
+  assert_eq!(g.region0_count, 8);
+  assert!(g.scalefac_scale);
+  assert!(g.count1table_select);
+  assert_eq!(r.position(), 59);
+}
+
+#[test]
+fn test_scale_factor_sharing() {
+  // slen1 = 2 and slen2 = 1 with the first and last groups shared.
+  let mut w = TestBitWriter::new();
+  for _ in 6..11 { w.write(3, 2); }
+  for _ in 11..16 { w.write(1, 1); }
+
+  let mut cursor = Cursor::new(w.bytes());
+  let mut r = bitreader::BitReader::new(&mut cursor);
+  let mut side_info = GranuleSideInfo::new();
+  side_info.scalefac_compress = 8;
+
+  let previous = [7u32; 22];
+  let mut data = GranuleData::new();
+
+  decode_scale_factors(&mut r, &side_info, &[true, false, false, true], Some(&previous), &mut data).unwrap();
+
+  assert_eq!(&data.scalefac_l[0..6], &[7, 7, 7, 7, 7, 7]);
+  assert_eq!(&data.scalefac_l[6..11], &[3, 3, 3, 3, 3]);
+  assert_eq!(&data.scalefac_l[11..16], &[1, 1, 1, 1, 1]);
+  assert_eq!(&data.scalefac_l[16..21], &[7, 7, 7, 7, 7]);
+  assert_eq!(r.position(), 15);
+}
+
+#[cfg(test)]
+struct TestBitWriter {
+  bits: Vec<u8>
+}
+
+#[cfg(test)]
+impl TestBitWriter {
+  fn new() -> TestBitWriter {
+    TestBitWriter { bits: Vec::new() }
+  }
+
+  fn write(&mut self, value: u32, n: u32) {
+    for i in (0..n).rev() {
+      self.bits.push(((value >> i) & 1) as u8);
+    }
+  }
+
+  fn bytes(&self) -> Vec<u8> {
+    let mut bytes = vec![0u8; self.bits.len().div_ceil(8)];
+
+    for (i, &bit) in self.bits.iter().enumerate() {
+      bytes[i / 8] |= bit << (7 - i % 8);
+    }
+
+    bytes
+  }
+}
+
+#[test]
+fn test_lsf_scale_factors() {
+  // scalefac_compress = 505 gives slen = 1, 2 with 11 and 10 long block
+  // bands, and preflag.
+  let mut w = TestBitWriter::new();
+  for _ in 0..11 { w.write(1, 1); }
+  for _ in 0..10 { w.write(2, 2); }
+
+  let mut cursor = Cursor::new(w.bytes());
+  let mut r = bitreader::BitReader::new(&mut cursor);
+  let mut side_info = GranuleSideInfo::new();
+  side_info.scalefac_compress = 505;
+
+  let mut data = GranuleData::new();
+
+  decode_lsf_scale_factors(&mut r, &side_info, false, &mut data).unwrap();
+
+  assert_eq!(data.scalefac_l[10], 1);
+  assert_eq!(data.scalefac_l[11], 2);
+  assert_eq!(data.scalefac_l[21], 0);
+  assert_eq!(data.illegal_position_l[10], 1);
+  assert_eq!(data.illegal_position_l[11], 3);
+  assert_eq!(r.position(), 31);
+}
+
+#[test]
+fn test_lsf_side_info() {
+  // MPEG-2 joint stereo with intensity stereo. Only the left channel takes
+  // preflag from scalefac_compress.
+  let mut w = TestBitWriter::new();
+  w.write(200, 8); w.write(0, 2);
+
+  for _ in 0..2 {
+    w.write(100, 12); w.write(20, 9); w.write(210, 8); w.write(505, 9); w.write(0, 1);
+    w.write(1, 5); w.write(2, 5); w.write(3, 5); w.write(4, 4); w.write(2, 3);
+    w.write(0, 1); w.write(1, 1);
+  }
+
+  let bytes = w.bytes();
+  assert_eq!(bytes.len(), 17);
+
+  let mut cursor = Cursor::new(bytes);
+  let mut r = bitreader::BitReader::new(&mut cursor);
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFF39050)).unwrap();
+  let side_info = decode_side_info(&mut r, &frame_header).unwrap();
+
+  assert_eq!(num_granules(&frame_header), 1);
+  assert_eq!(side_info.main_data_begin, 200);
+  assert_eq!(side_info.granules[0][0].scalefac_compress, 505);
+  assert!(side_info.granules[0][0].preflag);
+  assert!(!side_info.granules[0][1].preflag);
+  assert!(side_info.granules[0][1].count1table_select);
+}
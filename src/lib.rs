@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate bitflags;
+
+pub mod frame;
+pub mod header;
+mod bitreader;
+mod layer1;
+mod layer2;
+mod layer3;
+mod huffman;
+mod imdct;
+mod synthesis;
+pub mod decoder;
+pub mod crc;
+mod deemphasis;
+pub mod conceal;
+pub mod error;
+pub mod vbr;
+pub mod probe;
+pub mod id3;
+pub mod tags;
+
+pub use decoder::{Decoder, Next, StreamDecoder};
+pub use error::{Error, Result};
+pub use probe::{probe, BitrateMode, StreamInfo};
+pub use id3::write_tag;
@@ -1,44 +1,128 @@
-#[macro_use]
-extern crate bitflags;
+extern crate mp3;
 
 use std::env;
+use std::io;
+use std::io::Write;
 use std::path::Path;
 use std::fs::File;
 
-mod frame;
-mod header;
-mod peeker;
-mod bitreader;
-mod layer1;
+use mp3::{decoder, id3, probe, tags};
+
+fn write_pcm(output: &mut File, pcm: &[Vec<f64>]) {
+  let mut buf = Vec::with_capacity(pcm.len() * pcm[0].len() * 2);
+
+  for i in 0..pcm[0].len() {
+    for channel in pcm.iter() {
+      let sample = (channel[i] * 32768.0).clamp(-32768.0, 32767.0) as i16;
+
+      buf.push(sample as u8);
+      buf.push((sample >> 8) as u8);
+    }
+  }
+
+  match output.write_all(&buf) {
+    Ok(_) => {},
+    Err(e) => panic!("Unable to write PCM: {}", e)
+  }
+}
 
 fn main() {
   let args: Vec<String> = env::args().collect();
-  let path = Path::new(&args[1]);
-  let f = File::open(&path);
 
-  let mut reader = f.unwrap();
+  // With --probe the stream is described from its headers without decoding.
+  if args[1] == "--probe" {
+    match probe::probe(File::open(Path::new(&args[2])).unwrap()) {
+      Ok(info) => {
+        println!("{:?} {:?}, channel mode {}, {} Hz, {:?} bitrate", info.version, info.layer, info.channel_mode, info.samplerate, info.bitrate_mode);
+        println!("{} frames, {} samples, {:.3} seconds", info.frames, info.samples, info.duration);
+      },
+      Err(e) => eprintln!("Unable to probe: {}", e)
+    }
+
+    return;
+  }
+
+  // The stream is read from standard input when the path is "-".
+  let reader: Box<dyn io::Read> = if args[1] == "-" {
+    Box::new(io::stdin())
+  } else {
+    Box::new(File::open(Path::new(&args[1])).unwrap())
+  };
+
+  // Decoded audio is written as interleaved signed 16-bit little-endian PCM
+  // when an output path is given.
+  let mut output = if args.len() > 2 { Some(File::create(Path::new(&args[2])).unwrap()) } else { None };
+
+  let mut decoder = decoder::Decoder::new(reader);
+  let mut index = 0;
 
   loop  {
-    match frame::MpegFrame::read_from(&mut reader) {
+    match decoder.next_frame() {
       Ok(h) => match h {
-        Some(h) => {
+        Some(mut h) => {
           println!("Processing frame...");
-          let samples = layer1::decode_layer1(&mut reader, h.header);
-          // for i in range(0, 2) {
-          //   for j in range(0, 12) {
-          //     for k in range(0, 32) {
-          //       println!("ch = {}, sample = {}, sb = {}: {}", i, j, k, samples[i][j][k]);
-          //     }
-          //   }
-          // }
+
+          if index == 0 {
+            if let Some(tag) = decoder.id3v2() { for frame in tag.frames.iter() {
+              match *frame {
+                id3::Frame::Text { ref id, ref values } => println!("{}: {}", id, values.join(" / ")),
+                id3::Frame::UserText { ref description, ref value } => println!("TXXX {}: {}", description, value),
+                id3::Frame::Comment { ref description, ref text, .. } => println!("COMM {}: {}", description, text),
+                id3::Frame::Url { ref id, ref url } => println!("{}: {}", id, url),
+                id3::Frame::UserUrl { ref description, ref url } => println!("WXXX {}: {}", description, url),
+                id3::Frame::Picture(ref picture) => println!("APIC {}: {}, {} bytes", picture.picture_type, picture.mime_type, picture.data.len()),
+                id3::Frame::Chapter(ref chapter) => println!("CHAP {}: {} to {} ms", chapter.element_id, chapter.start_time, chapter.end_time),
+                id3::Frame::TableOfContents(ref toc) => println!("CTOC {}: {}", toc.element_id, toc.children.join(", ")),
+                id3::Frame::Other { ref id, ref data, .. } => println!("{}: {} bytes", id, data.len())
+              }
+            } }
+          }
+
+          if let Some(ref vbr) = h.vbr_header {
+            match vbr.duration(&h.header) {
+              Some(duration) => println!("{:?} header: {:.3} seconds", vbr.tag, duration),
+              None => println!("{:?} header", vbr.tag)
+            }
+
+            if let Some(ref lame) = vbr.lame { println!("{}: delay {}, padding {}", lame.encoder, lame.encoder_delay, lame.padding) }
+          }
+
+          if h.lost_frames > 0 {
+            eprintln!("Concealing {} lost frame(s) before frame {}", h.lost_frames, index);
+          }
+
+          let pcm = match decoder.decode_frame(&mut h) {
+            Ok(pcm) => pcm,
+            Err(e) => {
+              eprintln!("Unable to decode frame {}: {}", index, e);
+              index += 1;
+              continue;
+            }
+          };
+
+          if let Some(ref reason) = h.concealed { eprintln!("Concealed frame {}: {:?}", index, reason) }
+
+          if let Some(ref mut output) = output { write_pcm(output, &pcm) }
+
+          index += 1;
         },
         None => {
+          for tag in decoder.tags() {
+            match *tag {
+              tags::Tag::Id3v1(ref tag) => println!("ID3v1: {} / {} / {}", tag.title, tag.artist, tag.album),
+              tags::Tag::Lyrics3(ref tag) => println!("Lyrics3v{}: {} field(s)", tag.version, tag.fields.len()),
+              tags::Tag::Ape(ref tag) => println!("APEv2: {} item(s)", tag.items.len()),
+              tags::Tag::Id3v2(ref tag) => println!("ID3v2.{}: {} frame(s)", tag.version, tag.frames.len())
+            }
+          }
+
           println!("Complete.");
           break;
         }
       },
       Err(e) => {
-        panic!("Unhandled Error: {}", e);
+        eprintln!("Stopped at frame {}: {}", index, e);
+        break;
       },
     }
   }
@@ -0,0 +1,387 @@
+use std::io;
+use std::io::SeekFrom;
+
+use error;
+use header;
+use id3;
+use tags;
+use vbr;
+
+/// The number of bytes read at a time while looking for a frame.
+const READ_SIZE: usize = 4096;
+
+/// How the bitrate of a stream varies. Average bitrate streams vary like
+/// variable bitrate ones, and only a LAME tag tells them apart.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum BitrateMode {
+  Constant,
+  Average,
+  Variable
+}
+
+/// What `probe` finds out about a stream.
+#[derive(Debug,Clone,PartialEq)]
+pub struct StreamInfo {
+  pub version: header::MpegVersion,
+  pub layer: header::MpegLayer,
+  pub channel_mode: u32,
+  pub samplerate: u32,
+  pub bitrate_mode: BitrateMode,
+  /// The number of audio frames, not counting a frame holding a VBR header.
+  pub frames: u64,
+  /// The number of samples per channel, less the encoder delay and padding
+  /// when a LAME tag gives them.
+  pub samples: u64,
+  /// The duration in seconds.
+  pub duration: f64
+}
+
+/// Describes the stream read from `reader` without decoding it. The frame
+/// count comes from a Xing or VBRI header when the first frame holds one
+/// that has it. Otherwise only the frame headers are read, jumping from one
+/// to the next by their frame sizes, and the frame headers that follow are
+/// looked for where the stream is damaged.
+pub fn probe<R: io::Read + io::Seek>(mut reader: R) -> error::Result<StreamInfo> {
+  let length = match reader.seek(SeekFrom::End(0)) {
+    Ok(l) => l, Err(e) => return Err(error::Error::Io(e))
+  };
+
+  // An ID3v2 tag in front of the frames is jumped over.
+  let mut tag_header = [0u8; id3::HEADER_SIZE];
+
+  let read = match reader.seek(SeekFrom::Start(0)) {
+    Ok(_) => reader.read_exact(&mut tag_header),
+    Err(e) => Err(e)
+  };
+
+  let start = match read {
+    Ok(_) => id3::tag_size(&tag_header).unwrap_or(0) as u64,
+    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+    Err(e) => return Err(error::Error::Io(e))
+  };
+
+  let (offset, frame_header) = match find_frame(&mut reader, start, None) {
+    Ok(Some((o, h, _))) => (o, h),
+    Ok(None) => return Err(error::Error::InvalidHeader(None).at(0, 0)),
+    Err(e) => return Err(e)
+  };
+
+  // Only the first frame is read in full, for a VBR header.
+  let mut data = vec![0u8; frame_header.frame_size().unwrap_or(0) as usize];
+
+  let read = match reader.seek(SeekFrom::Start(offset)) {
+    Ok(_) => reader.read_exact(&mut data),
+    Err(e) => Err(e)
+  };
+
+  match read {
+    Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+  };
+
+  let vbr_header = vbr::find_vbr_header(&frame_header, &data);
+
+  let (count, constant) = match vbr_header {
+    Some(vbr::VbrHeader { frames: Some(count), .. }) => (count as u64, true),
+    _ => walk(&mut reader, &frame_header, vbr_header.is_some(), offset, length)?
+  };
+
+  let bitrate_mode = match vbr_header {
+    Some(ref vbr) => match (vbr.tag, vbr.lame.as_ref().map(|lame| lame.vbr_method)) {
+      (vbr::VbrTag::Info, _) | (_, Some(1)) | (_, Some(8)) => BitrateMode::Constant,
+      (_, Some(2)) | (_, Some(9)) => BitrateMode::Average,
+      _ => BitrateMode::Variable
+    },
+    None => if constant { BitrateMode::Constant } else { BitrateMode::Variable }
+  };
+
+  let samplerate = frame_header.samplerate.unwrap_or(0);
+  let mut samples = count * frame_header.frame_samples().unwrap_or(0) as u64;
+
+  if let Some(lame) = vbr_header.as_ref().and_then(|vbr| vbr.lame.as_ref()) {
+    let added = lame.encoder_delay as u64 + lame.padding as u64;
+
+    samples = samples.saturating_sub(added);
+  };
+
+  Ok(StreamInfo {
+    version: frame_header.version,
+    layer: frame_header.layer,
+    channel_mode: frame_header.channel_mode,
+    samplerate,
+    bitrate_mode,
+    frames: count,
+    samples,
+    duration: if samplerate > 0 { samples as f64 / samplerate as f64 } else { 0.0 }
+  })
+}
+
+/// Counts the frames from the one with header `first` at `offset` on to the
+/// end of the stream of `length` bytes, as many as the decoder puts out, and
+/// tells whether they all have the same bitrate. A first frame holding a VBR
+/// header is not counted.
+fn walk<R: io::Read + io::Seek>(reader: &mut R, first: &header::Header, vbr_header: bool, offset: u64, length: u64) -> error::Result<(u64, bool)> {
+  let mut count = if vbr_header { 0 } else { 1 };
+  let mut bitrate = if vbr_header { None } else { first.bitrate };
+  let mut constant = true;
+  let mut last_size = first.frame_size().unwrap_or(0) as u64;
+  let mut position = offset + last_size;
+
+  while position + 4 <= length {
+    let mut bytes = [0u8; 4];
+
+    let read = match reader.seek(SeekFrom::Start(position)) {
+      Ok(_) => reader.read_exact(&mut bytes),
+      Err(e) => Err(e)
+    };
+
+    match read {
+      Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+    };
+
+    let size = match header::Header::from_bytes(&bytes) {
+      Some(mut h) => {
+        if h.is_valid() && h.is_consistent_with(first) {
+          if let Some(s) = first.free_format_size { h.set_free_format_size(s) };
+
+          h.frame_size().map(|size| (size as u64, h.bitrate))
+        } else {
+          None
+        }
+      },
+      None => None
+    };
+
+    let (frame_size, frame_bitrate) = match size {
+      Some(s) => (s.0, s.1),
+      None => {
+        // Out of sync, the frames have to be looked for byte by byte. The
+        // junk skipped is counted as lost frames the way the decoder
+        // conceals them, unlike tags.
+        match find_frame(reader, position, first.free_format_size) {
+          Ok(Some((o, h, junk))) => {
+            count += (junk + last_size / 2) / last_size;
+            position = o;
+            (h.frame_size().unwrap_or(0) as u64, h.bitrate)
+          },
+          Ok(None) => break,
+          Err(e) => return Err(e)
+        }
+      }
+    };
+
+    if position + frame_size > length {
+      break;
+    }
+
+    match bitrate {
+      Some(b) => constant = constant && frame_bitrate == Some(b),
+      None => bitrate = frame_bitrate
+    };
+
+    count += 1;
+    last_size = frame_size;
+    position += frame_size;
+  }
+
+  Ok((count, constant))
+}
+
+/// What `check_frame` made of a possible frame.
+enum Candidate {
+  Frame(header::Header),
+  NeedMoreData,
+  Invalid
+}
+
+/// Finds the first frame at or after `offset`, returning its offset and
+/// header along with the number of bytes skipped that are not part of a
+/// tag, or None when the stream ends before. Only the headers are read, and
+/// a header is only taken when the header of the next frame or a tag
+/// follows its frame, or when its frame ends the stream. The frame size of
+/// free format frames is `free_format_size`, or else measured.
+fn find_frame<R: io::Read + io::Seek>(reader: &mut R, offset: u64, free_format_size: Option<u32>) -> error::Result<Option<(u64, header::Header, u64)>> {
+  match reader.seek(SeekFrom::Start(offset)) {
+    Ok(_) => {}, Err(e) => return Err(error::Error::Io(e))
+  };
+
+  let mut buffer = Vec::new();
+  let mut buffer_start = offset;
+  let mut start = offset;
+  let mut junk = 0;
+  let mut end = false;
+
+  loop {
+    let data = &buffer[(start - buffer_start) as usize..];
+
+    let skip = if data.len() < 4 && end {
+      return Ok(None);
+    } else if data.len() < 4 {
+      None
+    } else {
+      match tags::find_tag(data, end) {
+        tags::Found::Tag(size) => Some((size, false)),
+        tags::Found::NeedMoreData => None,
+        tags::Found::Nothing => match check_frame(data, end, free_format_size) {
+          Candidate::Frame(h) => return Ok(Some((start, h, junk))),
+          Candidate::NeedMoreData => None,
+          Candidate::Invalid => Some((1, true))
+        }
+      }
+    };
+
+    match skip {
+      Some((n, is_junk)) => {
+        start += n as u64;
+
+        if is_junk {
+          junk += n as u64;
+        }
+      },
+      None => {
+        buffer.drain(..(start - buffer_start) as usize);
+        buffer_start = start;
+
+        let mut chunk = [0u8; READ_SIZE];
+
+        match reader.read(&mut chunk) {
+          Ok(0) => end = true,
+          Ok(n) => buffer.extend(chunk[..n].iter().cloned()),
+          Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+          Err(e) => return Err(error::Error::Io(e))
+        };
+      }
+    };
+  }
+}
+
+/// Checks whether `data` starts with a frame the way `frame::FrameReader`
+/// does out of sync, without taking the frame.
+fn check_frame(data: &[u8], end: bool, free_format_size: Option<u32>) -> Candidate {
+  let mut frame_header = match header::Header::from_bytes(data) {
+    Some(h) if h.is_valid() => h,
+    _ => return Candidate::Invalid
+  };
+
+  if frame_header.free_format {
+    let size = match free_format_size {
+      Some(s) => Some(s),
+      None if data.len() < header::FREE_FORMAT_LOOKAHEAD && !end => return Candidate::NeedMoreData,
+      None => header::find_free_format_size(data, &frame_header)
+    };
+
+    match size {
+      Some(s) => frame_header.set_free_format_size(s),
+      None => return Candidate::Invalid
+    };
+  }
+
+  let frame_size = match frame_header.frame_size() {
+    Some(s) if s as usize >= frame_header.length() => s as usize,
+    _ => return Candidate::Invalid
+  };
+
+  if data.len() >= frame_size + 4 {
+    match header::Header::from_bytes(&data[frame_size..]) {
+      Some(ref h) if h.is_valid() && h.is_consistent_with(&frame_header) => {},
+      _ if tags::starts_tag(&data[frame_size..]) => {},
+      _ => return Candidate::Invalid
+    };
+  } else if !end {
+    return Candidate::NeedMoreData;
+  } else if data.len() != frame_size {
+    return Candidate::Invalid;
+  }
+
+  Candidate::Frame(frame_header)
+}
+
+#[cfg(test)]
+fn layer1_frame(bitrate_index: u8) -> Vec<u8> {
+  // A mono Layer I frame at 32 kHz, of 48 bytes per 32 kbit/s.
+  let mut frame = vec![0u8; 48 * bitrate_index as usize];
+
+  frame[..4].copy_from_slice(&[0xFF, 0xFF, bitrate_index << 4 | 0x08, 0xC0]);
+
+  frame
+}
+
+#[test]
+fn test_probe_cbr() {
+  // Junk before the frames, and a damaged frame that still counts.
+  let mut data = vec![0x12, 0x34, 0x56];
+
+  for _ in 0..10 {
+    data.extend(layer1_frame(2));
+  }
+
+  data[3 + 96 * 4 + 2] = 0xFF;
+
+  let info = probe(io::Cursor::new(&data)).unwrap();
+
+  assert_eq!(info.version, header::MpegVersion::MPEG1_0);
+  assert_eq!(info.layer, header::MpegLayer::LayerI);
+  assert_eq!(info.channel_mode, 3);
+  assert_eq!(info.samplerate, 32000);
+  assert_eq!(info.bitrate_mode, BitrateMode::Constant);
+  assert_eq!((info.frames, info.samples), (10, 3840));
+  assert_eq!(info.duration, 0.12);
+}
+
+#[test]
+fn test_probe_vbr() {
+  // The frames alternate between 32 and 64 kbit/s, and the last one is cut
+  // short.
+  let mut data = Vec::new();
+
+  for i in 0..10 {
+    data.extend(layer1_frame(1 + i % 2));
+  }
+
+  data.truncate(data.len() - 20);
+
+  let info = probe(io::Cursor::new(&data)).unwrap();
+
+  assert_eq!(info.bitrate_mode, BitrateMode::Variable);
+  assert_eq!((info.frames, info.samples), (9, 9 * 384));
+}
+
+#[test]
+fn test_probe_id3v2() {
+  // The tag holds what looks like a frame header.
+  let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x08\xFF\xFF\x18\xC0\x00\x00\x00\x00".to_vec();
+
+  for _ in 0..3 {
+    data.extend(layer1_frame(2));
+  }
+
+  assert_eq!(probe(io::Cursor::new(&data)).unwrap().frames, 3);
+}
+
+#[test]
+fn test_probe_xing() {
+  // Only the frame count of the Xing header is taken.
+  let mut data = vec![0u8; 417];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+  data[21..33].copy_from_slice(&[b'X', b'i', b'n', b'g', 0, 0, 0, 0x1, 0, 0, 0x10, 0]);
+
+  let info = probe(io::Cursor::new(&data)).unwrap();
+
+  assert_eq!(info.bitrate_mode, BitrateMode::Variable);
+  assert_eq!((info.frames, info.samples), (4096, 4096 * 1152));
+}
+
+#[test]
+fn test_probe_trailing_tags() {
+  let mut data = Vec::new();
+
+  for _ in 0..3 {
+    data.extend(layer1_frame(2));
+  }
+
+  data.extend(b"LYRICSBEGINHello LYRICSEND");
+  data.extend(b"TAG");
+  data.extend(&[0u8; 125]);
+
+  assert_eq!(probe(io::Cursor::new(&data)).unwrap().frames, 3);
+}
@@ -0,0 +1,438 @@
+use id3;
+
+/// The signatures that the tags found among the frames start with.
+const SIGNATURES: [&[u8]; 4] = [b"ID3", b"TAG", b"APETAGEX", b"LYRICSBEGIN"];
+
+const ID3V1_SIZE: usize = 128;
+const APE_HEADER_SIZE: usize = 32;
+
+/// The flag of an APEv2 header telling it from a footer.
+const APE_IS_HEADER: u32 = 1 << 29;
+
+/// The largest ID3v2 or APEv2 tag that is waited for. Larger sizes are
+/// taken for damage, as waiting for the rest of the tag would buffer the
+/// stream.
+pub const MAX_TAG_SIZE: usize = 1 << 24;
+
+/// The largest Lyrics3v2 tag, whose size is given in six digits, with its
+/// start and end markers.
+const LYRICS3_MAX_SIZE: usize = 999999 + 15;
+
+/// A tag found among the frames, which mostly means after the last one.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Tag {
+  Id3v2(id3::Tag),
+  Id3v1(Id3v1Tag),
+  Lyrics3(Lyrics3Tag),
+  Ape(ApeTag)
+}
+
+/// An ID3v1 tag, with the track number of ID3v1.1 when there is one. The
+/// genre is an index into the list of genres of ID3v1.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Id3v1Tag {
+  pub title: String,
+  pub artist: String,
+  pub album: String,
+  pub year: String,
+  pub comment: String,
+  pub track: Option<u8>,
+  pub genre: u8
+}
+
+/// A Lyrics3 tag. The lyrics of a Lyrics3v1 tag are given as its only field,
+/// with the ID "LYR" that Lyrics3v2 gives them.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Lyrics3Tag {
+  pub version: u8,
+  pub fields: Vec<(String, String)>
+}
+
+/// An APEv2 tag, with the version given by its header, such as 2000.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ApeTag {
+  pub version: u32,
+  pub items: Vec<(String, ApeValue)>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ApeValue {
+  /// UTF-8 text, which may hold several values.
+  Text(Vec<String>),
+  Binary(Vec<u8>),
+  /// The location of the value outside the tag.
+  Locator(String)
+}
+
+/// What `find_tag` found at the start of the data.
+pub enum Found {
+  /// A tag of that many bytes.
+  Tag(usize),
+  /// The data is too short to tell whether a tag starts it or where it ends.
+  NeedMoreData,
+  Nothing
+}
+
+/// Tells whether `data` starts with the signature of a tag, or with as much
+/// of one as it holds.
+pub fn starts_tag(data: &[u8]) -> bool {
+  SIGNATURES.iter().any(|signature| {
+    let n = data.len().min(signature.len());
+
+    n >= 3 && data[..n] == signature[..n]
+  })
+}
+
+/// Looks for an ID3v2, ID3v1, Lyrics3 or APEv2 tag at the start of `data`,
+/// where `end` says that nothing follows `data` in the stream. A tag cut
+/// short by the end of the stream takes the rest of it, except for an ID3v1
+/// tag, which is exactly the last 128 bytes of the stream. Only APEv2 tags
+/// with a header are found before their items, and a footer is taken on its
+/// own, leaving its items to `parse_ape_footer`.
+pub fn find_tag(data: &[u8], end: bool) -> Found {
+  if !starts_tag(data) {
+    return Found::Nothing;
+  }
+
+  let size = if data.starts_with(b"ID3") {
+    if data.len() < id3::HEADER_SIZE {
+      None
+    } else {
+      match id3::tag_size(data) {
+        Some(s) => Some(s),
+        None => return Found::Nothing
+      }
+    }
+  } else if data.starts_with(b"TAG") {
+    return match (data.len(), end) {
+      (ID3V1_SIZE, true) => Found::Tag(ID3V1_SIZE),
+      (n, false) if n <= ID3V1_SIZE => Found::NeedMoreData,
+      _ => Found::Nothing
+    };
+  } else if data.starts_with(b"APETAGEX") && data.len() >= APE_HEADER_SIZE {
+    if read_u32_le(&data[20..24]) & APE_IS_HEADER != 0 {
+      Some(APE_HEADER_SIZE + read_u32_le(&data[12..16]) as usize)
+    } else {
+      Some(APE_HEADER_SIZE)
+    }
+  } else if data.starts_with(b"LYRICSBEGIN") {
+    match lyrics3_end(data) {
+      Some(e) => Some(e),
+      None if data.len() > LYRICS3_MAX_SIZE => return Found::Nothing,
+      None => None
+    }
+  } else {
+    None
+  };
+
+  match size {
+    Some(s) if s > MAX_TAG_SIZE => Found::Nothing,
+    Some(s) if s <= data.len() => Found::Tag(s),
+    _ if !end => Found::NeedMoreData,
+    Some(_) => Found::Tag(data.len()),
+    None => Found::Nothing
+  }
+}
+
+/// Parses the tag that `find_tag` found at the start of `data`, which holds
+/// exactly that tag, or gives None when it cannot be read.
+pub fn parse(data: &[u8]) -> Option<Tag> {
+  if data.starts_with(b"ID3") {
+    return id3::parse(data).ok().map(Tag::Id3v2);
+  } else if data.starts_with(b"TAG") && data.len() == ID3V1_SIZE {
+    return Some(Tag::Id3v1(parse_id3v1(data)));
+  } else if data.starts_with(b"APETAGEX") {
+    return parse_ape(data).map(Tag::Ape);
+  } else if data.starts_with(b"LYRICSBEGIN") {
+    return parse_lyrics3(data).map(Tag::Lyrics3);
+  }
+
+  None
+}
+
+/// The size of the items in front of the APEv2 footer that starts `data`,
+/// which is found on its own when the tag has no header.
+pub fn ape_items_size(data: &[u8]) -> Option<usize> {
+  if !data.starts_with(b"APETAGEX") || data.len() < APE_HEADER_SIZE || read_u32_le(&data[20..24]) & APE_IS_HEADER != 0 {
+    return None;
+  }
+
+  (read_u32_le(&data[12..16]) as usize).checked_sub(APE_HEADER_SIZE)
+}
+
+/// Parses the APEv2 tag whose `footer` was found on its own after `items`,
+/// which holds exactly as many bytes as `ape_items_size` gives.
+pub fn parse_ape_footer(items: &[u8], footer: &[u8]) -> Option<Tag> {
+  if footer.len() < APE_HEADER_SIZE {
+    return None;
+  }
+
+  Some(Tag::Ape(ApeTag { version: read_u32_le(&footer[8..12]), items: parse_ape_items(items, read_u32_le(&footer[16..20])) }))
+}
+
+fn parse_id3v1(data: &[u8]) -> Id3v1Tag {
+  // ID3v1.1 takes the last byte of the comment for the track number.
+  let comment = &data[97..127];
+  let track = if comment[28] == 0 && comment[29] != 0 { Some(comment[29]) } else { None };
+
+  Id3v1Tag {
+    title: read_latin1(&data[3..33]),
+    artist: read_latin1(&data[33..63]),
+    album: read_latin1(&data[63..93]),
+    year: read_latin1(&data[93..97]),
+    comment: read_latin1(if track.is_some() { &comment[..28] } else { comment }),
+    track,
+    genre: data[127]
+  }
+}
+
+fn parse_ape(data: &[u8]) -> Option<ApeTag> {
+  if data.len() < APE_HEADER_SIZE {
+    return None;
+  }
+
+  let version = read_u32_le(&data[8..12]);
+
+  // The items of a footer found on its own are not in `data`.
+  if read_u32_le(&data[20..24]) & APE_IS_HEADER == 0 {
+    return Some(ApeTag { version, items: Vec::new() });
+  }
+
+  let end = if data.len() >= 2 * APE_HEADER_SIZE { data.len() - APE_HEADER_SIZE } else { data.len() };
+
+  Some(ApeTag { version, items: parse_ape_items(&data[APE_HEADER_SIZE..end], read_u32_le(&data[16..20])) })
+}
+
+/// Parses the first `count` items of an APEv2 tag from `data`, which holds
+/// its items alone.
+fn parse_ape_items(data: &[u8], count: u32) -> Vec<(String, ApeValue)> {
+  let mut items = Vec::new();
+  let end = data.len();
+  let mut position = 0;
+
+  for _ in 0..count {
+    if position + 8 > end {
+      break;
+    }
+
+    let size = read_u32_le(&data[position..position + 4]) as usize;
+    let flags = read_u32_le(&data[position + 4..position + 8]);
+
+    let key_end = match data[position + 8..end].iter().position(|&b| b == 0) {
+      Some(n) => position + 8 + n,
+      None => break
+    };
+
+    if size > end - key_end - 1 {
+      break;
+    }
+
+    let key = String::from_utf8_lossy(&data[position + 8..key_end]).into_owned();
+    let value = &data[key_end + 1..key_end + 1 + size];
+
+    items.push((key, match (flags >> 1) & 3 {
+      0 => ApeValue::Text(value.split(|&b| b == 0).map(|v| String::from_utf8_lossy(v).into_owned()).collect()),
+      2 => ApeValue::Locator(String::from_utf8_lossy(value).into_owned()),
+      _ => ApeValue::Binary(value.to_vec())
+    }));
+
+    position = key_end + 1 + size;
+  }
+
+  items
+}
+
+/// The size of the Lyrics3 tag starting `data`, up to the end marker of
+/// either version, when `data` holds all of it.
+fn lyrics3_end(data: &[u8]) -> Option<usize> {
+  data.windows(9).position(|w| w == b"LYRICSEND" || w == b"LYRICS200").map(|p| p + 9)
+}
+
+fn parse_lyrics3(data: &[u8]) -> Option<Lyrics3Tag> {
+  if data.len() < 20 {
+    return None;
+  }
+
+  // The lyrics of Lyrics3v1 run up to the end marker, and the fields of
+  // Lyrics3v2 up to the six digits of the size in front of it.
+  if data.ends_with(b"LYRICSEND") {
+    return Some(Lyrics3Tag { version: 1, fields: vec![("LYR".to_string(), read_latin1(&data[11..data.len() - 9]))] });
+  } else if !data.ends_with(b"LYRICS200") || data.len() < 26 {
+    return None;
+  }
+
+  let body = &data[11..data.len() - 15];
+  let mut tag = Lyrics3Tag { version: 2, fields: Vec::new() };
+  let mut position = 0;
+
+  while position + 8 <= body.len() {
+    let size = match read_digits(&body[position + 3..position + 8]) {
+      Some(s) if position + 8 + s <= body.len() => s,
+      _ => break
+    };
+
+    tag.fields.push((read_latin1(&body[position..position + 3]), read_latin1(&body[position + 8..position + 8 + size])));
+    position += 8 + size;
+  }
+
+  Some(tag)
+}
+
+/// Reads an ISO-8859-1 string padded with zero bytes or spaces.
+fn read_latin1(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+  let string: String = bytes[..end].iter().map(|&b| b as char).collect();
+
+  string.trim_end().to_string()
+}
+
+fn read_digits(bytes: &[u8]) -> Option<usize> {
+  let mut value = 0;
+
+  for &b in bytes {
+    if !b.is_ascii_digit() {
+      return None;
+    }
+
+    value = value * 10 + (b - b'0') as usize;
+  }
+
+  Some(value)
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+  (bytes[3] as u32) << 24 | (bytes[2] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[0] as u32
+}
+
+#[cfg(test)]
+fn ape_tag(items: &[(&str, u32, &[u8])]) -> Vec<u8> {
+  let mut data = Vec::new();
+
+  for &(key, flags, value) in items {
+    data.extend(&[value.len() as u8, 0, 0, 0, flags as u8, 0, 0, 0]);
+    data.extend(key.as_bytes());
+    data.push(0);
+    data.extend(value);
+  }
+
+  let size = data.len() + APE_HEADER_SIZE;
+  let mut tag = Vec::new();
+
+  for &flags in [0xA0u8, 0x80].iter() {
+    tag.extend(b"APETAGEX\xD0\x07\x00\x00");
+    tag.extend(&[size as u8, 0, 0, 0, items.len() as u8, 0, 0, 0, 0, 0, 0, flags, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    if flags == 0xA0 {
+      tag.extend(&data);
+    }
+  }
+
+  tag
+}
+
+#[test]
+fn test_id3v1() {
+  let mut data = vec![0u8; 128];
+
+  data[..8].copy_from_slice(b"TAGTitle");
+  data[33..39].copy_from_slice(b"Artist");
+  data[93..97].copy_from_slice(b"1999");
+  data[97..104].copy_from_slice(b"Comment");
+  data[126] = 7;
+  data[127] = 17;
+
+  match find_tag(&data, true) {
+    Found::Tag(128) => {},
+    _ => panic!("ID3v1 tag not found")
+  };
+
+  // Only the last 128 bytes of the stream are an ID3v1 tag.
+  match find_tag(&data, false) {
+    Found::NeedMoreData => {},
+    _ => panic!("ID3v1 tag found before the end")
+  };
+
+  match find_tag(&[&data[..], b"\xFF"].concat(), false) {
+    Found::Nothing => {},
+    _ => panic!("ID3v1 tag found before the last 128 bytes")
+  };
+
+  match find_tag(&data[..127], true) {
+    Found::Nothing => {},
+    _ => panic!("ID3v1 tag found in fewer than 128 bytes")
+  };
+
+  let tag = match parse(&data) {
+    Some(Tag::Id3v1(tag)) => tag,
+    _ => panic!("ID3v1 tag not parsed")
+  };
+
+  assert_eq!((tag.title.as_str(), tag.artist.as_str(), tag.album.as_str()), ("Title", "Artist", ""));
+  assert_eq!((tag.year.as_str(), tag.comment.as_str()), ("1999", "Comment"));
+  assert_eq!((tag.track, tag.genre), (Some(7), 17));
+}
+
+#[test]
+fn test_ape() {
+  let data = ape_tag(&[("Artist", 0, b"A\x00B"), ("Cover Art (Front)", 2, b"\xFF\xFB\x90\xC4"), ("Link", 4, b"file.txt")]);
+
+  match find_tag(&data, false) {
+    Found::Tag(s) => assert_eq!(s, data.len()),
+    _ => panic!("APEv2 tag not found")
+  };
+
+  assert_eq!(parse(&data), Some(Tag::Ape(ApeTag {
+    version: 2000,
+    items: vec![
+      ("Artist".to_string(), ApeValue::Text(vec!["A".to_string(), "B".to_string()])),
+      ("Cover Art (Front)".to_string(), ApeValue::Binary(vec![0xFF, 0xFB, 0x90, 0xC4])),
+      ("Link".to_string(), ApeValue::Locator("file.txt".to_string()))
+    ]
+  })));
+
+  // A size past what is waited for is taken for damage.
+  let mut damaged = data.clone();
+  damaged[12..16].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+
+  match find_tag(&damaged, false) {
+    Found::Nothing => {},
+    _ => panic!("APEv2 tag of 4 GB waited for")
+  };
+
+  // The footer on its own, with the items in front of it.
+  let (items, footer) = (&data[32..data.len() - 32], &data[data.len() - 32..]);
+
+  match find_tag(footer, true) {
+    Found::Tag(32) => {},
+    _ => panic!("APEv2 footer not found")
+  };
+
+  assert_eq!(ape_items_size(footer), Some(items.len()));
+  assert_eq!(ape_items_size(&data), None);
+  assert_eq!(parse_ape_footer(items, footer), parse(&data));
+}
+
+#[test]
+fn test_lyrics3() {
+  let data = b"LYRICSBEGININD0000210LYR00005Hello000034LYRICS200";
+
+  match find_tag(&data[..30], false) {
+    Found::NeedMoreData => {},
+    _ => panic!("Lyrics3 tag found without its end")
+  };
+
+  assert_eq!(parse(data), Some(Tag::Lyrics3(Lyrics3Tag {
+    version: 2,
+    fields: vec![("IND".to_string(), "10".to_string()), ("LYR".to_string(), "Hello".to_string())]
+  })));
+
+  assert_eq!(parse(b"LYRICSBEGINHello LYRICSEND"), Some(Tag::Lyrics3(Lyrics3Tag {
+    version: 1,
+    fields: vec![("LYR".to_string(), "Hello".to_string())]
+  })));
+
+  match find_tag(b"LYRICSBEGIN", true) {
+    Found::Nothing => {},
+    _ => panic!("Lyrics3 tag found without its end")
+  };
+}
@@ -0,0 +1,318 @@
+use header;
+use header::MpegLayer::*;
+use layer3;
+
+/// The tag a VBR header starts with. Info is the tag of the Xing header
+/// written by LAME for constant bitrate streams.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum VbrTag {
+  Xing,
+  Info,
+  Vbri
+}
+
+/// The seek table of a VBR header.
+#[derive(Debug,Clone,PartialEq)]
+pub enum SeekTable {
+  /// The 100 entries of a Xing header. Entry i is where the stream is at i
+  /// percent of its duration, in 256ths of its byte count.
+  Percent(Vec<u8>),
+  /// The entries of a VBRI header, each the size in bytes of the next
+  /// `frames` frames.
+  Frames { frames: u32, sizes: Vec<u32> }
+}
+
+/// A Xing, Info or VBRI header, held by the first frame of a stream in place
+/// of audio to describe the whole stream.
+#[derive(Debug,Clone,PartialEq)]
+pub struct VbrHeader {
+  pub tag: VbrTag,
+  /// The number of audio frames, not counting the frame of the header.
+  pub frames: Option<u32>,
+  /// The size of the stream in bytes.
+  pub bytes: Option<u32>,
+  pub seek_table: Option<SeekTable>,
+  /// The encoder quality, from 0 (best) to 100 (worst) for Xing headers.
+  pub quality: Option<u32>,
+  pub lame: Option<LameTag>
+}
+
+/// The extension LAME and FFmpeg add after the fields of a Xing header.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LameTag {
+  /// The encoder and its version, such as "LAME3.99r".
+  pub encoder: String,
+  pub revision: u8,
+  pub vbr_method: u8,
+  /// The lowpass filter frequency in Hz.
+  pub lowpass: Option<u32>,
+  /// The ReplayGain peak amplitude, 1.0 being full scale, and the track and
+  /// album gains in dB.
+  pub peak: Option<f64>,
+  pub track_gain: Option<f64>,
+  pub album_gain: Option<f64>,
+  /// The number of samples the encoder added before and after the audio.
+  pub encoder_delay: u32,
+  pub padding: u32,
+  /// The number of bytes from the start of the frame of the header to the
+  /// end of the audio, and the CRC-16 of the audio frames.
+  pub music_length: u32,
+  pub music_crc: u16
+}
+
+impl VbrHeader {
+  /// The duration of the stream in seconds, from the number of frames and
+  /// the header of any of them.
+  pub fn duration(&self, frame_header: &header::Header) -> Option<f64> {
+    match (self.frames, frame_header.frame_samples(), frame_header.samplerate) {
+      (Some(frames), Some(samples), Some(samplerate)) => Some(frames as f64 * samples as f64 / samplerate as f64),
+      _ => None
+    }
+  }
+
+  /// Estimates from the seek table of a Xing header how far audio frame
+  /// `frame` is from the start of the frame of the header, interpolating
+  /// between the entries for the percentages around it.
+  pub fn seek_offset(&self, frame: u64) -> Option<u64> {
+    let (toc, frames, bytes) = match (&self.seek_table, self.frames, self.bytes) {
+      (&Some(SeekTable::Percent(ref toc)), Some(frames), Some(bytes)) if toc.len() == 100 && frame < frames as u64 => (toc, frames, bytes),
+      _ => return None
+    };
+
+    let percent = frame as f64 * 100.0 / frames as f64;
+    let entry = percent as usize;
+    let lower = toc[entry] as f64;
+    let upper = if entry < 99 { toc[entry + 1] as f64 } else { 256.0 };
+    let position = lower + (upper - lower) * (percent - entry as f64);
+
+    Some((position / 256.0 * bytes as f64) as u64)
+  }
+}
+
+fn read_u16(data: &[u8]) -> u32 {
+  ((data[0] as u32) << 8) | data[1] as u32
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+  ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32
+}
+
+/// Takes the next `length` bytes of `data` at `position`, if there are that
+/// many left.
+fn read_field<'a>(data: &'a [u8], position: &mut usize, length: usize) -> Option<&'a [u8]> {
+  if *position + length > data.len() {
+    return None;
+  }
+
+  *position += length;
+
+  Some(&data[*position - length..*position])
+}
+
+/// Looks for a VBR header in the Layer III frame `data`, which starts with
+/// `frame_header`. A Xing header follows the side information, and a VBRI
+/// header is 32 bytes past the header.
+pub fn find_vbr_header(frame_header: &header::Header, data: &[u8]) -> Option<VbrHeader> {
+  if frame_header.layer != LayerIII {
+    return None;
+  }
+
+  let xing = frame_header.length() + layer3::side_info_length(frame_header);
+
+  if data.len() >= xing + 8 {
+    match &data[xing..xing + 4] {
+      b"Xing" => return parse_xing(VbrTag::Xing, &data[xing + 4..]),
+      b"Info" => return parse_xing(VbrTag::Info, &data[xing + 4..]),
+      _ => {}
+    };
+  }
+
+  if data.len() >= 36 + 26 && &data[36..40] == b"VBRI" {
+    return parse_vbri(&data[40..]);
+  }
+
+  None
+}
+
+/// Parses a Xing header after its tag: flags saying which of the frame
+/// count, byte count, seek table and quality follow, in that order.
+fn parse_xing(tag: VbrTag, data: &[u8]) -> Option<VbrHeader> {
+  let flags = read_u32(data);
+  let mut position = 4;
+  let mut header = VbrHeader { tag, frames: None, bytes: None, seek_table: None, quality: None, lame: None };
+
+  if flags & 0x1 != 0 {
+    header.frames = match read_field(data, &mut position, 4) { Some(f) => Some(read_u32(f)), None => return None };
+  }
+
+  if flags & 0x2 != 0 {
+    header.bytes = match read_field(data, &mut position, 4) { Some(f) => Some(read_u32(f)), None => return None };
+  }
+
+  if flags & 0x4 != 0 {
+    header.seek_table = match read_field(data, &mut position, 100) { Some(f) => Some(SeekTable::Percent(f.to_vec())), None => return None };
+  }
+
+  if flags & 0x8 != 0 {
+    header.quality = match read_field(data, &mut position, 4) { Some(f) => Some(read_u32(f)), None => return None };
+  }
+
+  header.lame = match read_field(data, &mut position, 36) { Some(f) => parse_lame_tag(f), None => None };
+
+  Some(header)
+}
+
+/// Parses the 36 bytes of a LAME tag, if the encoder is one that writes it.
+fn parse_lame_tag(data: &[u8]) -> Option<LameTag> {
+  match &data[..4] {
+    b"LAME" | b"Lavc" | b"Lavf" => {},
+    _ => return None
+  };
+
+  let encoder = data[..9].iter().take_while(|&&b| b != 0).map(|&b| b as char).collect::<String>();
+  let peak = read_u32(&data[11..]);
+  let delay_padding = (read_u16(&data[21..]) << 8) | data[23] as u32;
+
+  Some(LameTag {
+    encoder: encoder.trim().to_string(),
+    revision: data[9] >> 4,
+    vbr_method: data[9] & 0xF,
+    lowpass: if data[10] != 0 { Some(data[10] as u32 * 100) } else { None },
+    peak: if peak != 0 { Some(peak as f64 / (1 << 23) as f64) } else { None },
+    track_gain: parse_replay_gain(read_u16(&data[15..]), 1),
+    album_gain: parse_replay_gain(read_u16(&data[17..]), 2),
+    encoder_delay: delay_padding >> 12,
+    padding: delay_padding & 0xFFF,
+    music_length: read_u32(&data[28..]),
+    music_crc: read_u16(&data[32..]) as u16
+  })
+}
+
+/// Parses a ReplayGain field of the LAME tag, whose name code is 1 for the
+/// track gain and 2 for the album gain, followed by the originator, the
+/// sign and the gain in tenths of a dB.
+fn parse_replay_gain(field: u32, name: u32) -> Option<f64> {
+  if field >> 13 != name {
+    return None;
+  }
+
+  let gain = (field & 0x1FF) as f64 / 10.0;
+
+  Some(if field & 0x200 != 0 { -gain } else { gain })
+}
+
+/// Parses a VBRI header after its tag: version, delay, quality, byte and
+/// frame counts, then the seek table with its number of entries, their
+/// scale, the size of each entry and the number of frames per entry.
+fn parse_vbri(data: &[u8]) -> Option<VbrHeader> {
+  let entries = read_u16(&data[14..]) as usize;
+  let scale = read_u16(&data[16..]);
+  let entry_size = read_u16(&data[18..]) as usize;
+  let frames_per_entry = read_u16(&data[20..]);
+
+  if !(1..=4).contains(&entry_size) || data.len() < 22 + entries * entry_size {
+    return None;
+  }
+
+  let sizes = data[22..22 + entries * entry_size].chunks(entry_size).map(|entry| {
+    entry.iter().fold(0, |size, &b| (size << 8) | b as u32).wrapping_mul(scale)
+  }).collect();
+
+  Some(VbrHeader {
+    tag: VbrTag::Vbri,
+    frames: Some(read_u32(&data[10..])),
+    bytes: Some(read_u32(&data[6..])),
+    seek_table: Some(SeekTable::Frames { frames: frames_per_entry, sizes }),
+    quality: Some(read_u16(&data[4..])),
+    lame: None
+  })
+}
+
+#[test]
+fn test_xing() {
+  // A mono MPEG-1 frame, whose side information is 17 bytes, with frame
+  // and byte counts and the quality.
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB90C4)).unwrap();
+  let mut data = vec![0u8; 417];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+  data[21..25].copy_from_slice(b"Xing");
+  data[25..29].copy_from_slice(&[0, 0, 0, 0xB]);
+  data[29..33].copy_from_slice(&[0, 0, 0x01, 0x00]);
+  data[33..37].copy_from_slice(&[0, 0x01, 0x00, 0x00]);
+  data[37..41].copy_from_slice(&[0, 0, 0, 57]);
+
+  let vbr = find_vbr_header(&frame_header, &data).unwrap();
+
+  assert_eq!(vbr, VbrHeader { tag: VbrTag::Xing, frames: Some(256), bytes: Some(65536), seek_table: None, quality: Some(57), lame: None });
+  assert!((vbr.duration(&frame_header).unwrap() - 256.0 * 1152.0 / 44100.0).abs() < 1e-9);
+
+  data[21..25].copy_from_slice(b"Nope");
+
+  assert_eq!(find_vbr_header(&frame_header, &data), None);
+}
+
+#[test]
+fn test_vbri() {
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB9064)).unwrap();
+  let mut data = vec![0u8; 417];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+  data[36..40].copy_from_slice(b"VBRI");
+  data[40..62].copy_from_slice(&[
+    0, 1,             // Version
+    0x04, 0x60,       // Delay
+    0, 75,            // Quality
+    0, 0, 0x30, 0x00, // Bytes
+    0, 0, 0, 40,      // Frames
+    0, 2,             // Entries
+    0, 2,             // Scale
+    0, 2,             // Entry size
+    0, 20             // Frames per entry
+  ]);
+  data[62..66].copy_from_slice(&[0x0B, 0x00, 0x0D, 0x00]);
+
+  let vbr = find_vbr_header(&frame_header, &data).unwrap();
+
+  assert_eq!(vbr.tag, VbrTag::Vbri);
+  assert_eq!(vbr.frames, Some(40));
+  assert_eq!(vbr.bytes, Some(0x3000));
+  assert_eq!(vbr.quality, Some(75));
+  assert_eq!(vbr.seek_table, Some(SeekTable::Frames { frames: 20, sizes: vec![0x1600, 0x1A00] }));
+}
+
+#[test]
+fn test_lame_tag() {
+  let frame_header = header::Header::from_binary(&header::BinaryHeader::from_bits_truncate(0xFFFB90C4)).unwrap();
+  let mut data = vec![0u8; 417];
+
+  data[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+  data[21..25].copy_from_slice(b"Info");
+  data[25..29].copy_from_slice(&[0, 0, 0, 0x1]);
+  data[29..33].copy_from_slice(&[0, 0, 0, 40]);
+  data[33..69].copy_from_slice(&[
+    b'L', b'A', b'M', b'E', b'3', b'.', b'9', b'9', b'r',
+    0x10,                   // Revision 1, method 0
+    195,                    // Lowpass
+    0x00, 0x80, 0x00, 0x00, // Peak
+    0x2E, 0x3B,             // Track gain, -5.9 dB
+    0x00, 0x00,             // Album gain
+    0, 0,                   // Flags, bitrate
+    0x24, 0x03, 0xE8,       // Delay 576, padding 1000
+    0, 0, 0, 0,             // Misc, MP3 gain, preset
+    0, 0, 0x30, 0x00,       // Music length
+    0xAB, 0xCD,             // Music CRC
+    0, 0                    // Tag CRC
+  ]);
+
+  let lame = find_vbr_header(&frame_header, &data).unwrap().lame.unwrap();
+
+  assert_eq!(lame.encoder, "LAME3.99r");
+  assert_eq!((lame.revision, lame.vbr_method), (1, 0));
+  assert_eq!(lame.lowpass, Some(19500));
+  assert_eq!(lame.peak, Some(1.0));
+  assert_eq!(lame.track_gain, Some(-5.9));
+  assert_eq!(lame.album_gain, None);
+  assert_eq!((lame.encoder_delay, lame.padding), (576, 1000));
+  assert_eq!((lame.music_length, lame.music_crc), (0x3000, 0xABCD));
+}